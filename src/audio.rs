@@ -0,0 +1,277 @@
+use crate::{consts::*, render::View};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use lewton::inside_ogg::OggStreamReader;
+use macroquad::{
+    audio::{PlaySoundParams, Sound, load_sound_from_bytes, play_sound},
+    file::load_file,
+    prelude::*,
+};
+use parry3d_f64::{
+    math::{Isometry, Point, Vector},
+    query::{Ray, RayCast},
+    shape::Compound,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, f32::consts::FRAC_PI_4, io::Cursor};
+
+/// Everything that makes a sound, named as in `assets/sounds.toml`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cue {
+    Gunshot,
+    Reload,
+    Empty,
+    HitMarker,
+    Death,
+    Jump,
+    Land,
+    /// Landing from high enough to be slowed down or hurt.
+    HardLanding,
+    Footstep,
+}
+
+impl Cue {
+    const ALL: [Cue; 9] = [
+        Cue::Gunshot,
+        Cue::Reload,
+        Cue::Empty,
+        Cue::HitMarker,
+        Cue::Death,
+        Cue::Jump,
+        Cue::Land,
+        Cue::HardLanding,
+        Cue::Footstep,
+    ];
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    #[default]
+    Sfx,
+    Ui,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Volume {
+    pub master: f32,
+    pub sfx: f32,
+    pub ui: f32,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            sfx: 1.0,
+            ui: 1.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    file: String,
+    #[serde(default)]
+    channel: Channel,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    volume: Volume,
+    cues: HashMap<Cue, Entry>,
+}
+
+/// Decodes an ogg or wav file into mono samples and their sample rate.
+fn decode(bytes: &[u8]) -> Result<(Vec<i16>, u32), String> {
+    let (samples, channels, rate) = if bytes.starts_with(b"OggS") {
+        let mut reader = OggStreamReader::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+        let channels = reader.ident_hdr.audio_channels as usize;
+        let rate = reader.ident_hdr.audio_sample_rate;
+
+        let mut samples = Vec::new();
+        while let Some(packet) = reader.read_dec_packet_itl().map_err(|e| e.to_string())? {
+            samples.extend(packet);
+        }
+        (samples, channels, rate)
+    } else {
+        let mut reader = WavReader::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+        let spec = reader.spec();
+        let samples = reader
+            .samples::<i16>()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        (samples, spec.channels as usize, spec.sample_rate)
+    };
+
+    let mono = samples
+        .chunks_exact(channels)
+        .map(|frame| {
+            (frame.iter().map(|&sample| sample as i32).sum::<i32>() / channels as i32) as i16
+        })
+        .collect();
+    Ok((mono, rate))
+}
+
+/// A stereo wav with the samples in one channel and silence in the other.
+fn encode(samples: &[i16], rate: u32, channel: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut writer = WavWriter::new(
+        Cursor::new(&mut bytes),
+        WavSpec {
+            channels: 2,
+            sample_rate: rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        },
+    )
+    .unwrap();
+
+    for &sample in samples {
+        for current in 0..2 {
+            writer
+                .write_sample(if current == channel { sample } else { 0 })
+                .unwrap();
+        }
+    }
+    writer.finalize().unwrap();
+
+    bytes
+}
+
+/// A sound split into a left-only and a right-only copy, panned by playing both at different
+/// volumes since macroquad has no panning of its own.
+struct Spatial {
+    left: Sound,
+    right: Sound,
+}
+
+impl Spatial {
+    async fn load(path: &str) -> Result<Self, String> {
+        let (samples, rate) = decode(&load_file(path).await.map_err(|e| e.to_string())?)?;
+
+        Ok(Self {
+            left: load_sound_from_bytes(&encode(&samples, rate, 0))
+                .await
+                .map_err(|e| e.to_string())?,
+            right: load_sound_from_bytes(&encode(&samples, rate, 1))
+                .await
+                .map_err(|e| e.to_string())?,
+        })
+    }
+
+    /// `pan` goes from -1 (left) to 1 (right).
+    fn play(&self, volume: f32, pan: f32) {
+        // Equal power so that the sound isn't quieter in the middle
+        let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+        for (sound, gain) in [(&self.left, angle.cos()), (&self.right, angle.sin())] {
+            play_sound(
+                sound,
+                PlaySoundParams {
+                    looped: false,
+                    volume: volume * gain,
+                },
+            );
+        }
+    }
+}
+
+/// Volume and pan of a sound at `position`, `None` when it's out of earshot.
+pub fn hear(listener: &View, compound: &Compound, position: DVec3) -> Option<(f32, f32)> {
+    let offset = position - listener.eye;
+    let distance = offset.length();
+    if distance >= HEARING_DISTANCE {
+        return None;
+    }
+    if distance < f64::EPSILON {
+        return Some((1.0, 0.0));
+    }
+
+    let direction = offset / distance;
+    let mut volume = (1.0 - distance / HEARING_DISTANCE).powi(2) as f32;
+
+    // Walls between the listener and the sound muffle it
+    let blocked = compound
+        .cast_ray(
+            &Isometry::identity(),
+            &Ray::new(
+                Point::new(listener.eye.x, listener.eye.y, listener.eye.z),
+                Vector::new(direction.x, direction.y, direction.z),
+            ),
+            distance,
+            true,
+        )
+        .is_some();
+    if blocked {
+        volume *= MUFFLE_VOLUME;
+    }
+
+    Some((volume, direction.dot(listener.right) as f32))
+}
+
+/// All the sounds of the game. Anything that fails to load is reported once and stays silent.
+pub struct Sounds {
+    pub volume: Volume,
+    sounds: HashMap<Cue, (Spatial, Channel)>,
+}
+
+impl Sounds {
+    pub async fn load(path: &str) -> Self {
+        let manifest = match load_string(path).await {
+            Ok(manifest) => toml::from_str::<Manifest>(&manifest).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let manifest = manifest.unwrap_or_else(|e| {
+            eprintln!("Warning: couldn't load {path}, playing no sounds: {e}");
+            Manifest {
+                volume: Volume::default(),
+                cues: HashMap::new(),
+            }
+        });
+
+        let mut sounds = HashMap::new();
+        for cue in Cue::ALL {
+            let Some(entry) = manifest.cues.get(&cue) else {
+                eprintln!("Warning: no sound for {cue:?} in {path}");
+                continue;
+            };
+            match Spatial::load(&entry.file).await {
+                Ok(sound) => {
+                    sounds.insert(cue, (sound, entry.channel));
+                }
+                Err(e) => eprintln!("Warning: couldn't load {} for {cue:?}: {e}", entry.file),
+            }
+        }
+
+        Self {
+            volume: manifest.volume,
+            sounds,
+        }
+    }
+
+    pub fn play(&self, cue: Cue, volume: f32, pan: f32) {
+        if let Some((sound, channel)) = self.sounds.get(&cue) {
+            let channel_volume = match channel {
+                Channel::Sfx => self.volume.sfx,
+                Channel::Ui => self.volume.ui,
+            };
+            sound.play(volume * channel_volume * self.volume.master, pan);
+        }
+    }
+
+    /// Plays the sound as heard by `listener` from `position`.
+    pub fn play_at(
+        &self,
+        cue: Cue,
+        listener: &View,
+        compound: &Compound,
+        position: DVec3,
+        volume: f32,
+    ) {
+        if let Some((attenuation, pan)) = hear(listener, compound, position) {
+            self.play(cue, volume * attenuation, pan);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Timings of map and hitbox queries as maps grow, next to testing every box in turn. Ignored by
+//! default, run with `cargo test --release benches -- --ignored --nocapture --test-threads 1`.
+use crate::{
+    consts::*,
+    hitboxes::Hitboxes,
+    map::isometry,
+    model::{Pose, bones},
+    movement::Movement,
+    player::Player,
+    round::Team,
+};
+use ::rand::{Rng, SeedableRng, rngs::StdRng};
+use macroquad::prelude::*;
+use parry3d_f64::{
+    math::{Isometry, Point, Vector},
+    query::{Ray, RayCast},
+    shape::{Compound, Cuboid, SharedShape},
+};
+use std::{collections::HashMap, f64::consts::TAU, hint::black_box, time::Instant};
+
+const ITERATIONS: usize = 1000;
+
+/// Side of a square map holding `count` boxes about as densely as the arena.
+fn side(count: usize) -> f64 {
+    (count as f64).sqrt() * 4.0
+}
+
+/// `count` crates of random sizes and turns.
+fn map(count: usize, rng: &mut StdRng) -> Compound {
+    let half = side(count) / 2.0;
+    Compound::new(
+        (0..count)
+            .map(|_| {
+                let center = dvec3(
+                    rng.random_range(-half..half),
+                    rng.random_range(0.0..2.0),
+                    rng.random_range(-half..half),
+                );
+                let rotation = DQuat::from_rotation_y(rng.random_range(0.0..TAU));
+                (
+                    isometry(center, rotation),
+                    SharedShape::cuboid(
+                        rng.random_range(0.2..1.5),
+                        rng.random_range(0.1..1.0),
+                        rng.random_range(0.2..1.5),
+                    ),
+                )
+            })
+            .collect(),
+    )
+}
+
+fn direction(rng: &mut StdRng) -> DVec3 {
+    let angle = rng.random_range(0.0..TAU);
+    dvec3(angle.cos(), 0.0, angle.sin())
+}
+
+fn ray(origin: DVec3, direction: DVec3) -> Ray {
+    Ray::new(
+        Point::new(origin.x, origin.y, origin.z),
+        Vector::new(direction.x, direction.y, direction.z),
+    )
+}
+
+fn time(name: &str, mut f: impl FnMut(usize)) {
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        f(i);
+    }
+    println!("{name}: {:?}", start.elapsed() / ITERATIONS as u32);
+}
+
+#[test]
+#[ignore]
+fn map_queries() {
+    let movement = Movement::default();
+
+    for count in [10, 1_000, 10_000] {
+        let mut rng = StdRng::seed_from_u64(count as u64);
+        let compound = map(count, &mut rng);
+        let half = side(count) / 2.0;
+        let starts = (0..ITERATIONS)
+            .map(|_| {
+                (
+                    dvec3(
+                        rng.random_range(-half..half),
+                        PLAYER_SIZE.y,
+                        rng.random_range(-half..half),
+                    ),
+                    direction(&mut rng),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut player = Player::new(DVec3::ZERO, Team::Red);
+        time(&format!("{count} boxes, a frame of movement"), |i| {
+            let (position, direction) = starts[i];
+            player.position = position;
+            player.velocity = direction * movement.max_speed;
+            player.airborne = false;
+            player.collide(&compound, &movement);
+            black_box(player.position);
+        });
+
+        let rays = starts
+            .iter()
+            .map(|&(position, direction)| ray(position, direction))
+            .collect::<Vec<_>>();
+        time(&format!("{count} boxes, a bullet"), |i| {
+            black_box(compound.cast_ray(&Isometry::identity(), &rays[i], BULLET_RANGE, true));
+        });
+        time(&format!("{count} boxes, a bullet against every box"), |i| {
+            black_box(
+                compound
+                    .shapes()
+                    .iter()
+                    .filter_map(|(isometry, shape)| {
+                        shape.cast_ray(isometry, &rays[i], BULLET_RANGE, true)
+                    })
+                    .reduce(f64::min),
+            );
+        });
+    }
+}
+
+#[test]
+#[ignore]
+fn hitbox_queries() {
+    let mut rng = StdRng::seed_from_u64(0);
+
+    for count in [2, 10, 30] {
+        let peers = (0..count)
+            .map(|i| {
+                let mut peer = Player::new(DVec3::ZERO, Team::Blue);
+                peer.pose = Pose::Run;
+                let start = dvec3(rng.random_range(5.0..20.0), PLAYER_SIZE.y, 0.0);
+                let start = DQuat::from_rotation_y(rng.random_range(0.0..TAU)) * start;
+                let heading = direction(&mut rng);
+                // A second of running
+                peer.ticks = (0..TICKS_PER_SECOND)
+                    .map(|tick| Some(start + heading * tick as f64 * MOVE_SPEED))
+                    .collect();
+                (format!("127.0.0.1:{}", 1000 + i).parse().unwrap(), peer)
+            })
+            .collect::<HashMap<_, _>>();
+        let rays = (0..ITERATIONS)
+            .map(|_| ray(dvec3(0.0, 1.5, 0.0), direction(&mut rng)))
+            .collect::<Vec<_>>();
+
+        time(&format!("{count} enemies, building hitboxes"), |_| {
+            black_box(Hitboxes::new(&peers, Team::Red, 0.0));
+        });
+        let hitboxes = Hitboxes::new(&peers, Team::Red, 0.0);
+        time(&format!("{count} enemies, a pellet"), |i| {
+            black_box(hitboxes.cast_ray(&rays[i], BULLET_RANGE));
+        });
+        time(
+            &format!("{count} enemies, a pellet against every bone"),
+            |i| {
+                for peer in peers.values() {
+                    for position in peer.ticks.iter().flatten() {
+                        for bone in bones(*position, peer.yaw, peer.pitch, peer.pose, 0.0) {
+                            let half_extents = bone.half_extents;
+                            black_box(
+                                Cuboid::new(Vector::new(
+                                    half_extents.x,
+                                    half_extents.y,
+                                    half_extents.z,
+                                ))
+                                .cast_ray(
+                                    &bone.isometry(),
+                                    &rays[i],
+                                    BULLET_RANGE,
+                                    true,
+                                ),
+                            );
+                        }
+                    }
+                }
+            },
+        );
+    }
+}
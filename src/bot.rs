@@ -0,0 +1,346 @@
+use crate::{
+    consts::*,
+    nav::Nav,
+    player::{Controls, Player},
+    weapon::seconds,
+};
+use ::rand::{Rng, SeedableRng, rngs::StdRng};
+use macroquad::prelude::*;
+use parry3d_f64::{
+    math::{Isometry, Point, Vector},
+    query::{Ray, RayCast},
+    shape::Compound,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    f64::consts::{PI, TAU},
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+/// How well bots play, per level from `assets/bots.toml`.
+#[derive(Clone, Deserialize)]
+pub struct Skill {
+    /// How long an enemy has to be in sight before being fired at.
+    #[serde(deserialize_with = "seconds")]
+    pub reaction_time: Duration,
+    /// Largest angle the aim is off by, picked anew for every shot.
+    pub aim_error: f64,
+    /// Radians per second.
+    pub turn_speed: f64,
+    /// How long to strafe one way while fighting.
+    #[serde(deserialize_with = "seconds")]
+    pub strafe_interval: Duration,
+    /// Shortest time between two pulls of the trigger.
+    #[serde(deserialize_with = "seconds")]
+    pub tap_interval: Duration,
+}
+
+impl Skill {
+    pub async fn load(path: &str, level: &str) -> Self {
+        let mut levels: HashMap<String, Self> =
+            toml::from_str(&load_string(path).await.unwrap()).expect("Invalid bots file.");
+        levels
+            .remove(level)
+            .unwrap_or_else(|| panic!("Unknown bot skill {level}."))
+    }
+}
+
+/// Whether nothing of the map is between `from` and `to`.
+fn sees(compound: &Compound, from: DVec3, to: DVec3) -> bool {
+    let distance = from.distance(to);
+    let direction = (to - from) / distance;
+    compound
+        .cast_ray(
+            &Isometry::identity(),
+            &Ray::new(
+                Point::new(from.x, from.y, from.z),
+                Vector::new(direction.x, direction.y, direction.z),
+            ),
+            distance,
+            true,
+        )
+        .is_none()
+}
+
+/// Where bots aim on a player.
+fn chest(player: &Player) -> DVec3 {
+    player.position + DVec3::Y * BOT_AIM_HEIGHT
+}
+
+/// A player simulated by the host. Its body lives in the peers like that of a remote player, and
+/// what it does is sent from its own socket so that everyone else takes it for one.
+pub struct Bot {
+    pub socket: UdpSocket,
+    pub addr: SocketAddr,
+    skill: Skill,
+    /// Enemy being fought, with when it came into sight.
+    target: Option<(SocketAddr, Instant)>,
+    /// `(yaw, pitch)` the aim is off by.
+    aim_error: DVec2,
+    /// When the last shot the aim error was picked for was fired.
+    last_shot: Option<Instant>,
+    /// When the trigger was last pulled.
+    press_timestamp: Option<Instant>,
+    /// Along `right` while fighting, -1 or 1.
+    strafe: f64,
+    strafe_timestamp: Instant,
+    /// Waypoints still to go, the next one last.
+    path: Vec<DVec3>,
+    /// When `path` was found, `None` to find one right away.
+    path_timestamp: Option<Instant>,
+    rng: StdRng,
+}
+
+impl Bot {
+    pub fn new(socket: UdpSocket, skill: Skill, rng: &mut StdRng) -> Self {
+        Self {
+            addr: socket.local_addr().unwrap(),
+            socket,
+            skill,
+            target: None,
+            aim_error: DVec2::ZERO,
+            last_shot: None,
+            press_timestamp: None,
+            strafe: 1.0,
+            strafe_timestamp: Instant::now(),
+            path: Vec::new(),
+            path_timestamp: None,
+            rng: StdRng::seed_from_u64(rng.random()),
+        }
+    }
+
+    /// Picks how far off the next shot is, anywhere within the aim error of the skill.
+    fn miss(&mut self) {
+        let angle = self.rng.random_range(0.0..TAU);
+        let radius = self.skill.aim_error * self.rng.random::<f64>().sqrt();
+        self.aim_error = dvec2(angle.cos(), angle.sin()) * radius;
+    }
+
+    /// The closest enemy that can be hurt in sight, or the current target while it stays in
+    /// sight even if it isn't in front anymore.
+    fn spot(
+        &self,
+        body: &Player,
+        everyone: &HashMap<SocketAddr, Player>,
+        compound: &Compound,
+    ) -> Option<SocketAddr> {
+        let eye = body.eye();
+        everyone
+            .iter()
+            .filter(|(_, enemy)| !enemy.killed && !enemy.protected && enemy.team != body.team)
+            .filter(|(addr, enemy)| {
+                let kept = self.target.is_some_and(|(target, _)| target == **addr);
+                let towards = (chest(enemy) - eye).normalize_or_zero();
+                (kept || body.front.angle_between(towards) <= BOT_VIEW_ANGLE)
+                    && sees(compound, eye, chest(enemy))
+            })
+            .min_by(|(_, a), (_, b)| {
+                a.position
+                    .distance_squared(body.position)
+                    .total_cmp(&b.position.distance_squared(body.position))
+            })
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Turns towards `yaw` and `pitch` as fast as the skill allows, returning the angle still
+    /// left to turn.
+    fn turn(&self, body: &mut Player, yaw: f64, pitch: f64, delta: f64) -> f64 {
+        let step = self.skill.turn_speed * delta;
+        let yaw_left = (yaw - body.yaw + PI).rem_euclid(TAU) - PI;
+        let pitch_left = pitch - body.pitch;
+        body.yaw += yaw_left.clamp(-step, step);
+        body.pitch += pitch_left.clamp(-step, step);
+        body.orient();
+        (yaw_left.abs() - step)
+            .max(pitch_left.abs() - step)
+            .max(0.0)
+    }
+
+    /// Decides what to do this frame and turns `body` to aim or to walk. Fights the enemies it
+    /// sees and otherwise heads for the closest one over `nav`.
+    pub fn think(
+        &mut self,
+        body: &mut Player,
+        everyone: &HashMap<SocketAddr, Player>,
+        compound: &Compound,
+        nav: &Nav,
+        delta: f64,
+    ) -> Controls {
+        let mut controls = Controls::default();
+
+        let spotted = self.spot(body, everyone, compound);
+        if spotted != self.target.map(|(target, _)| target) {
+            self.target = spotted.map(|target| (target, Instant::now()));
+            self.miss();
+        }
+        if body.last_bullet_timestamp != self.last_shot {
+            self.last_shot = body.last_bullet_timestamp;
+            self.miss();
+        }
+
+        if let Some((target, seen_timestamp)) = self.target {
+            let to = chest(&everyone[&target]) - body.eye();
+            let left = self.turn(
+                body,
+                to.z.atan2(to.x) + self.aim_error.x,
+                (to.y / to.length()).asin() + self.aim_error.y,
+                delta,
+            );
+
+            if self.strafe_timestamp.elapsed() >= self.skill.strafe_interval {
+                self.strafe = -self.strafe;
+                self.strafe_timestamp = Instant::now();
+            }
+            controls.strafe = self.strafe;
+
+            let fire = seen_timestamp.elapsed() >= self.skill.reaction_time
+                && left <= BOT_FIRE_ANGLE
+                && body.reload_timestamp.is_none();
+            controls.fire = fire;
+            // Pulled anew no faster than the skill allows, which paces semi-automatic weapons
+            controls.fire_pressed = fire
+                && self
+                    .press_timestamp
+                    .is_none_or(|timestamp| timestamp.elapsed() >= self.skill.tap_interval);
+            if controls.fire_pressed {
+                self.press_timestamp = Some(Instant::now());
+            }
+
+            // The enemies will have moved by the end of the fight
+            self.path_timestamp = None;
+            return controls;
+        }
+
+        // Nothing to fight, so a good time to reload
+        controls.reload = true;
+
+        if self
+            .path_timestamp
+            .is_none_or(|timestamp| timestamp.elapsed() >= BOT_REPATH)
+        {
+            self.path_timestamp = Some(Instant::now());
+            let goal = everyone
+                .values()
+                .filter(|enemy| !enemy.killed && enemy.team != body.team)
+                .map(|enemy| enemy.position)
+                .min_by(|a, b| {
+                    a.distance_squared(body.position)
+                        .total_cmp(&b.distance_squared(body.position))
+                });
+            self.path = goal
+                .and_then(|goal| nav.path(body.position, goal))
+                .unwrap_or_default();
+            self.path.reverse();
+        }
+
+        while self.path.last().is_some_and(|waypoint| {
+            (*waypoint - body.position).with_y(0.0).length() < BOT_WAYPOINT_RADIUS
+        }) {
+            self.path.pop();
+        }
+
+        if let Some(waypoint) = self.path.last() {
+            let to = (*waypoint - body.position).with_y(0.0);
+            self.turn(body, to.z.atan2(to.x), 0.0, delta);
+
+            // Along the way whichever way it faces, it doesn't have to have turned yet
+            let to = to.normalize_or_zero();
+            controls.forward = to.dot(body.front.with_y(0.0).normalize_or_zero());
+            controls.strafe = to.dot(body.right);
+        }
+
+        controls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        map::{Triggers, isometry},
+        round::Team,
+    };
+    use parry3d_f64::shape::SharedShape;
+    use std::thread::sleep;
+
+    fn bot() -> Bot {
+        Bot::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            Skill {
+                reaction_time: Duration::from_millis(50),
+                aim_error: 0.0,
+                turn_speed: 100.0,
+                strafe_interval: Duration::from_secs(1),
+                tap_interval: Duration::from_secs(1),
+            },
+            &mut StdRng::seed_from_u64(0),
+        )
+    }
+
+    fn compound(wall: bool) -> Compound {
+        // Somewhere out of the way without the wall
+        let center = if wall {
+            dvec3(2.5, 1.0, 0.0)
+        } else {
+            dvec3(0.0, -5.0, 0.0)
+        };
+        Compound::new(vec![(
+            isometry(center, DQuat::IDENTITY),
+            SharedShape::cuboid(0.2, 1.0, 2.0),
+        )])
+    }
+
+    fn think(bot: &mut Bot, body: &mut Player, enemy: &Player, wall: bool) -> Controls {
+        let compound = compound(wall);
+        let nav = Nav::new(&compound, &Triggers::new(Vec::new(), Vec::new()), 6.0);
+        let everyone = HashMap::from([("127.0.0.1:5001".parse().unwrap(), enemy.clone())]);
+        bot.think(body, &everyone, &compound, &nav, 1.0 / 60.0)
+    }
+
+    #[test]
+    fn fires_at_enemies_in_sight_only_once_it_reacted() {
+        let mut bot = bot();
+        let mut body = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        let enemy = Player::new(dvec3(5.0, PLAYER_SIZE.y, 0.5), Team::Blue);
+
+        let controls = think(&mut bot, &mut body, &enemy, false);
+        assert!(!controls.fire);
+        assert_ne!(controls.strafe, 0.0);
+        // Aimed at right away with how fast it turns
+        let towards = (chest(&enemy) - body.eye()).normalize();
+        assert!(body.front.angle_between(towards) < 1e-6);
+
+        sleep(Duration::from_millis(60));
+        let controls = think(&mut bot, &mut body, &enemy, false);
+        assert!(controls.fire && controls.fire_pressed);
+        // Held, but not pulled again before the tap interval
+        let controls = think(&mut bot, &mut body, &enemy, false);
+        assert!(controls.fire && !controls.fire_pressed);
+
+        body.reload_timestamp = Some(Instant::now());
+        assert!(!think(&mut bot, &mut body, &enemy, false).fire);
+        body.reload_timestamp = None;
+
+        // Lost behind a wall, and gone after instead
+        let controls = think(&mut bot, &mut body, &enemy, true);
+        assert!(!controls.fire);
+        assert!(bot.target.is_none());
+        assert!(!bot.path.is_empty());
+        assert!(controls.forward != 0.0 || controls.strafe != 0.0);
+    }
+
+    #[test]
+    fn doesnt_see_behind_itself() {
+        let mut bot = bot();
+        let mut body = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        let enemy = Player::new(dvec3(-5.0, PLAYER_SIZE.y, 0.0), Team::Blue);
+        think(&mut bot, &mut body, &enemy, false);
+        assert!(bot.target.is_none());
+
+        let teammate = Player::new(dvec3(5.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        think(&mut bot, &mut body, &teammate, false);
+        assert!(bot.target.is_none());
+    }
+}
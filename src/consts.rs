@@ -1,12 +1,19 @@
 use macroquad::prelude::*;
 use parry3d_f64::math::Vector;
 use std::{
-    f64::consts::{FRAC_PI_2, PI},
+    f64::consts::{FRAC_PI_2, FRAC_PI_4},
     sync::LazyLock,
     time::Duration,
 };
 
+/// The HUD is laid out for this size and scaled to the actual one.
 pub const DEFAULT_SCREEN_SIZE: Vec2 = vec2(1920.0, 1080.0);
+/// Scale of the whole HUD on top of following the screen size.
+pub const HUD_SCALE: f32 = 1.0;
+/// Distance of the HUD from the edges of the screen.
+pub const HUD_MARGIN: f32 = 10.0;
+/// Between lines of HUD text.
+pub const HUD_SPACING: f32 = 4.0;
 pub const FOV: f32 = std::f32::consts::FRAC_PI_2;
 
 pub const PITCH_BOUND: f64 = FRAC_PI_2 * 0.999;
@@ -18,6 +25,63 @@ pub const WORLD_UP: DVec3 = DVec3::Y;
 pub const JUMP_VELOCITY: f64 = 0.06;
 pub const GRAVITY: f64 = 0.0035;
 
+// Default movement rules, speeds are per frame
+/// Share of the wanted speed gained per frame on the ground.
+pub const GROUND_ACCELERATION: f64 = 0.1;
+/// Share of the speed lost per frame on the ground.
+pub const FRICTION: f64 = 0.08;
+/// Below this, friction acts as if moving this fast so that the player comes to a stop.
+pub const STOP_SPEED: f64 = 0.015;
+pub const AIR_ACCELERATION: f64 = 0.2;
+/// Speed aimed for while in the air, low so that turning while strafing gains speed.
+pub const AIR_SPEED: f64 = 0.006;
+/// Share of the top speed under which shooting is accurate.
+pub const ACCURATE_SPEED: f64 = 0.34;
+/// Landing speed that doesn't hurt, from about 3 meters.
+pub const FALL_DAMAGE_SPEED: f64 = 0.145;
+/// Health lost per unit of landing speed beyond `FALL_DAMAGE_SPEED`.
+pub const FALL_DAMAGE: f64 = 800.0;
+/// Landing speed from which the player is slowed down, from about 1.5 meters.
+pub const HARD_LANDING_SPEED: f64 = 0.1;
+/// Share of the speed left right after a hard landing.
+pub const LANDING_SLOWDOWN: f64 = 0.5;
+pub const LANDING_SLOWDOWN_DURATION: Duration = Duration::from_millis(400);
+pub const LADDER_SPEED: f64 = 0.03;
+/// How long a map event stays on screen.
+pub const NOTICE_DURATION: Duration = Duration::from_secs(2);
+/// Free camera speed per frame.
+pub const SPECTATOR_SPEED: f64 = 0.15;
+/// Free camera speed per frame while holding Shift.
+pub const SPECTATOR_FAST_SPEED: f64 = 0.5;
+/// Distance of the third-person camera from the eyes of the followed player.
+pub const ORBIT_DISTANCE: f64 = 3.0;
+/// Kept between the third-person camera and a wall behind it.
+pub const ORBIT_MARGIN: f64 = 0.2;
+/// Replayed through the eyes of the killer after being shot dead.
+pub const KILLCAM_DURATION: Duration = Duration::from_secs(4);
+/// Side of the cells of the grid bots find their way on.
+pub const NAV_CELL: f64 = 0.5;
+/// Highest ledge bots drop down from, low enough not to slow them down.
+pub const NAV_DROP: f64 = 1.0;
+/// Bot skill level used unless `BOT_SKILL` says otherwise.
+pub const DEFAULT_BOT_SKILL: &str = "normal";
+/// Height above `Player::position` bots aim at, the middle of the torso.
+pub const BOT_AIM_HEIGHT: f64 = 0.15;
+/// Farthest from where bots face that they notice enemies, in radians.
+pub const BOT_VIEW_ANGLE: f64 = 1.0;
+/// How far off their aim bots still fire, in radians.
+pub const BOT_FIRE_ANGLE: f64 = 0.05;
+/// How often bots find a new way to the closest enemy.
+pub const BOT_REPATH: Duration = Duration::from_secs(1);
+/// How close bots get to a waypoint before heading for the next.
+pub const BOT_WAYPOINT_RADIUS: f64 = 0.2;
+/// Seconds skipped by seeking through a demo.
+pub const DEMO_SEEK: f64 = 5.0;
+pub const DEMO_MIN_SPEED: f64 = 0.25;
+pub const DEMO_MAX_SPEED: f64 = 8.0;
+/// Movement rules used unless `MODE` says otherwise.
+pub const DEFAULT_MODE: &str = "competitive";
+
 pub const TICKS_PER_SECOND: usize = 64;
 pub static DURATION_PER_TICK: LazyLock<Duration> =
     LazyLock::new(|| Duration::from_secs(1) / TICKS_PER_SECOND as u32);
@@ -28,18 +92,119 @@ pub const WALKING_SPEED_CONST: f64 = 0.5;
 pub const CAMERA_Y: f64 = 1.0;
 pub const PLAYER_SIZE: Vector<f64> = Vector::new(0.1 / 2.0, CAMERA_Y / 2.0, 0.1 / 2.0);
 pub const CROUCH_LEVEL_CONST: f64 = 0.3 * CAMERA_Y;
+/// Tallest ledge walked onto without jumping.
+pub const STEP_HEIGHT: f64 = 0.25;
+/// Steepest surface that can be stood on, from the horizontal.
+pub const MAX_SLOPE: f64 = FRAC_PI_4;
+/// How far below the soles still counts as standing.
+pub const GROUND_PROBE: f64 = 0.01;
+/// Times the player is pushed out of the map per move, once per surface touched.
+pub const COLLISION_ITERATIONS: usize = 4;
+/// How much smaller the player is when swept through the map, so that it doesn't catch on
+/// what it stands on.
+pub const SWEEP_SKIN: f64 = 0.005;
+/// Precision of finding the ground under a step, each halves the error.
+pub const SETTLE_ITERATIONS: usize = 16;
+
+pub const CROSSHAIR_CODE_PREFIX: &str = "XH-";
 
-pub const CROSSHAIR_LINE_LENGTH: f32 = 8.0;
-pub const CROSSHAIR_THICKNESS: f32 = 3.0;
-pub const CROSSHAIR_COLOR: Color = DARKGREEN;
+pub const HIT_MARKER_DURATION: Duration = Duration::from_millis(250);
+/// Length of each line of the hit marker.
+pub const HIT_MARKER_SIZE: f32 = 8.0;
+/// Distance from the middle of the screen to where the hit marker lines start.
+pub const HIT_MARKER_GAP: f32 = 6.0;
+pub const HIT_MARKER_THICKNESS: f32 = 3.0;
+pub const HIT_MARKER_COLOR: Color = WHITE;
+pub const KILL_MARKER_SCALE: f32 = 1.5;
+pub const KILL_MARKER_COLOR: Color = RED;
+pub const DAMAGE_ARC_DURATION: Duration = Duration::from_secs(1);
+/// Relative to the screen height.
+pub const DAMAGE_ARC_RADIUS: f32 = 0.12;
+pub const DAMAGE_ARC_THICKNESS: f32 = 6.0;
+/// In degrees.
+pub const DAMAGE_ARC_SPAN: f32 = 40.0;
+pub const DAMAGE_ARC_SIDES: u8 = 64;
+pub const DAMAGE_ARC_COLOR: Color = Color::new(0.9, 0.1, 0.1, 1.0);
 
-pub static BULLET_INTERVAL: Duration = Duration::from_millis(100);
-pub static BULLET_SPREAD: f64 = PI / 10.0;
-pub static BULLET_SPREAD_PERIOD: Duration = Duration::from_secs(10);
-pub const BULLETS_BEFORE_RELOAD: u8 = 30;
-pub const RELOAD_DURATION: Duration = Duration::from_secs(2);
 pub const BULLETS_FONT_SIZE: u16 = 35;
+/// Relative to the height of the bullets text.
+pub const RELOAD_BAR_HEIGHT: f32 = 0.25;
+pub const MAX_HEALTH: f64 = 100.0;
+
+/// Side of the radar relative to the screen height, unless `RADAR_SIZE` is set.
+pub const DEFAULT_RADAR_SIZE: f32 = 0.25;
+/// Meters from the middle of the radar to its edge, unless `RADAR_RANGE` is set.
+pub const DEFAULT_RADAR_RANGE: f32 = 20.0;
+/// What the range is multiplied by at each zoom level, cycled through with Z.
+pub const RADAR_ZOOMS: [f32; 3] = [1.0, 2.0, 0.5];
+/// Radius of the player dots in pixels at `DEFAULT_SCREEN_SIZE`.
+pub const RADAR_DOT_SIZE: f32 = 4.0;
+pub const RADAR_BACKGROUND: Color = Color::new(0.0, 0.0, 0.0, 0.5);
+pub const RADAR_WALL_COLOR: Color = LIGHTGRAY;
+/// How long enemies show up on the radar after firing.
+pub const RADAR_REVEAL_DURATION: Duration = Duration::from_secs(2);
+
+pub const PACKET_SIZE: usize = 1024;
+/// How long a joining player waits for the whole answer before registering again.
+pub const JOIN_RETRY: Duration = Duration::from_secs(1);
+
+/// Random spots of a spawn rectangle weighed against each other.
+pub const SPAWN_CANDIDATES: usize = 16;
+/// How far from teammates a spawn has to be to count as free.
+pub const SPAWN_CLEARANCE: f64 = 1.0;
+/// How long a spawned player can't be hurt by others, unless it shoots first.
+pub const SPAWN_PROTECTION_DURATION: Duration = Duration::from_secs(3);
+pub const BUY_DURATION: Duration = Duration::from_secs(15);
+pub const ROUND_DURATION: Duration = Duration::from_secs(115);
+pub const ROUND_OVER_DURATION: Duration = Duration::from_secs(5);
+pub const ROUNDS_PER_HALF: u8 = 12;
+pub const ROUNDS_TO_WIN: u8 = ROUNDS_PER_HALF + 1;
+pub const ROUND_FONT_SIZE: u16 = 35;
+
+pub const START_MONEY: u32 = 800;
+pub const MAX_MONEY: u32 = 16000;
+pub const KILL_REWARD: u32 = 300;
+pub const ROUND_WIN_REWARD: u32 = 3250;
+pub const ROUND_LOSS_REWARD: u32 = 1400;
+
+pub const BULLET_RANGE: f64 = 1000.0;
+pub const TRACER_DURATION: Duration = Duration::from_millis(150);
+pub const TRACER_COLOR: Color = YELLOW;
+pub const IMPACT_DURATION: Duration = Duration::from_secs(5);
+pub const IMPACT_SIZE: f32 = 0.04;
+pub const MAX_IMPACTS: usize = 256;
+pub const FLASH_DURATION: Duration = Duration::from_millis(50);
+pub const FLASH_SIZE: f32 = 0.03;
+/// Offset of the local muzzle from the camera along `front`, `right` and `up`.
+pub const MUZZLE_OFFSET: DVec3 = DVec3::new(0.3, 0.08, -0.08);
+
+/// Sounds farther away than this aren't heard at all.
+pub const HEARING_DISTANCE: f64 = 60.0;
+/// Volume factor of sounds behind a wall.
+pub const MUFFLE_VOLUME: f32 = 0.3;
+pub const FOOTSTEP_STRIDE: f64 = 1.0;
+pub const FOOTSTEP_VOLUME: f32 = 0.6;
+pub const WALKING_FOOTSTEP_VOLUME: f32 = 0.15;
+
+/// Height of the eyes above `Player::position` when standing.
+pub const EYE_OFFSET: f64 = LEG_LENGTH + TORSO_LENGTH + HEAD_SIZE - CAMERA_Y / 2.0;
+pub const HEADSHOT_MULTIPLIER: f64 = 4.0;
 
-pub const SIZE: f32 = 5.0;
-pub const COLUMNS: usize = 10;
-pub const HALF: f64 = COLUMNS as f64 * SIZE as f64 / 2.0;
+// Player model, all sizes but lengths are half extents
+pub const LEG_LENGTH: f64 = 0.4;
+pub const TORSO_LENGTH: f64 = 0.4;
+pub const ARM_LENGTH: f64 = 0.3;
+pub const GUN_LENGTH: f64 = 0.25;
+pub const HEAD_SIZE: f64 = 0.08;
+pub const TORSO_WIDTH: f64 = 0.08;
+pub const TORSO_DEPTH: f64 = 0.05;
+pub const LIMB_WIDTH: f64 = 0.035;
+pub const ARM_WIDTH: f64 = 0.025;
+pub const GUN_WIDTH: f64 = 0.02;
+/// Distance of each leg from the middle.
+pub const LIMB_SPACING: f64 = 0.04;
+pub const RUN_CYCLE_SPEED: f64 = 10.0;
+pub const RUN_LEG_SWING: f64 = 0.6;
+pub const JUMP_LEG_TUCK: f64 = 0.5;
+/// Half extents of a box around the player that the model fits in whatever the pose.
+pub const HITBOX_BOUNDS: DVec3 = DVec3::splat(LEG_LENGTH + TORSO_LENGTH + HEAD_SIZE * 2.0);
@@ -0,0 +1,186 @@
+use crate::{consts::*, hud::Layout};
+use bincode::{Decode, Encode, config};
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Encode, Decode)]
+#[serde(rename_all = "snake_case")]
+pub enum Style {
+    Cross,
+    /// A cross without the top line.
+    T,
+    Dot,
+    Circle,
+}
+
+/// How the crosshair looks, from `assets/crosshair.toml` or a share code.
+#[derive(Clone, PartialEq, Debug, Deserialize, Encode, Decode)]
+#[serde(default)]
+pub struct Crosshair {
+    pub style: Style,
+    pub color: [u8; 3],
+    pub opacity: f32,
+    /// Length of the lines, in pixels at `DEFAULT_SCREEN_SIZE`.
+    pub length: f32,
+    pub thickness: f32,
+    /// Distance from the middle of the screen to the lines, or the radius of the circle.
+    pub gap: f32,
+    /// Width of the black outline, none when 0.
+    pub outline: f32,
+    /// Whether the gap widens with the spread of the weapon.
+    pub dynamic: bool,
+}
+
+impl Default for Crosshair {
+    fn default() -> Self {
+        Self {
+            style: Style::Cross,
+            color: [0, 117, 44],
+            opacity: 1.0,
+            length: 8.0,
+            thickness: 3.0,
+            gap: 0.0,
+            outline: 0.0,
+            dynamic: false,
+        }
+    }
+}
+
+impl Crosshair {
+    /// Falls back to the default crosshair when the file is missing or invalid.
+    pub async fn load(path: &str) -> Self {
+        let crosshair = match load_string(path).await {
+            Ok(crosshair) => toml::from_str(&crosshair).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        crosshair.unwrap_or_else(|e| {
+            eprintln!("Warning: couldn't load {path}, using the default crosshair: {e}");
+            Self::default()
+        })
+    }
+
+    /// A short string teammates can paste to get the same crosshair.
+    pub fn code(&self) -> String {
+        let bytes = bincode::encode_to_vec(self, config::standard()).unwrap();
+        let hex = bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        format!("{CROSSHAIR_CODE_PREFIX}{hex}")
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        let hex = code.trim().strip_prefix(CROSSHAIR_CODE_PREFIX)?;
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        bincode::decode_from_slice(&bytes, config::standard())
+            .ok()
+            .map(|(crosshair, _)| crosshair)
+    }
+
+    /// `spread` is the half-angle of the current cone of fire in radians, only used when
+    /// `dynamic`.
+    pub fn draw(&self, layout: &Layout, spread: f64) {
+        let center = layout.center();
+        let gap = if self.dynamic {
+            // Where the edge of the cone lands on the screen
+            self.gap * layout.scale
+                + (spread.tan() / (FOV as f64 / 2.0).tan()) as f32 * layout.size.y / 2.0
+        } else {
+            self.gap * layout.scale
+        };
+
+        let color = Color::from_rgba(
+            self.color[0],
+            self.color[1],
+            self.color[2],
+            (self.opacity.clamp(0.0, 1.0) * 255.0) as u8,
+        );
+        let outline = Color {
+            a: color.a,
+            ..BLACK
+        };
+
+        if self.outline > 0.0 {
+            self.draw_shape(layout, center, gap, self.outline, outline);
+        }
+        self.draw_shape(layout, center, gap, 0.0, color);
+    }
+
+    /// `grow` widens every stroke on both sides, which draws the outline. `gap` is already
+    /// scaled.
+    fn draw_shape(&self, layout: &Layout, center: Vec2, gap: f32, grow: f32, color: Color) {
+        let [length, thickness, grow] =
+            [self.length, self.thickness, grow].map(|size| size * layout.scale);
+        let thickness = thickness + grow * 2.0;
+        match self.style {
+            Style::Cross | Style::T => {
+                let directions: &[Vec2] = if self.style == Style::Cross {
+                    &[
+                        vec2(1.0, 0.0),
+                        vec2(-1.0, 0.0),
+                        vec2(0.0, 1.0),
+                        vec2(0.0, -1.0),
+                    ]
+                } else {
+                    &[vec2(1.0, 0.0), vec2(-1.0, 0.0), vec2(0.0, 1.0)]
+                };
+                for &direction in directions {
+                    let from = center + direction * (gap - grow);
+                    let to = center + direction * (gap + length + grow);
+                    draw_line(from.x, from.y, to.x, to.y, thickness, color);
+                }
+            }
+            Style::Dot => draw_rectangle(
+                center.x - thickness / 2.0,
+                center.y - thickness / 2.0,
+                thickness,
+                thickness,
+                color,
+            ),
+            Style::Circle => {
+                draw_circle_lines(center.x, center.y, gap.max(thickness), thickness, color)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        let crosshair = Crosshair {
+            style: Style::Circle,
+            color: [255, 0, 128],
+            opacity: 0.5,
+            gap: 4.0,
+            outline: 1.0,
+            dynamic: true,
+            ..Default::default()
+        };
+        let code = crosshair.code();
+        assert!(code.starts_with(CROSSHAIR_CODE_PREFIX));
+        assert_eq!(Crosshair::from_code(&code), Some(crosshair.clone()));
+        assert_eq!(Crosshair::from_code(&format!(" {code}\n")), Some(crosshair));
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let code = Crosshair::default().code();
+        assert_eq!(
+            Crosshair::from_code(&code[CROSSHAIR_CODE_PREFIX.len()..]),
+            None
+        );
+        assert_eq!(Crosshair::from_code(&code[..code.len() - 1]), None);
+        assert_eq!(Crosshair::from_code(&format!("{code}zz")), None);
+        assert_eq!(Crosshair::from_code(&code[..code.len() - 2]), None);
+        assert_eq!(Crosshair::from_code("XH-é0"), None);
+    }
+}
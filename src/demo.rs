@@ -0,0 +1,330 @@
+use crate::{consts::*, player::Player, round::Team};
+use bincode::{Decode, Encode, config, decode_from_slice, encode_into_std_write};
+use macroquad::prelude::*;
+use std::{
+    collections::HashMap,
+    fs::{File, read},
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    time::Instant,
+};
+
+/// Written at the start of a demo file.
+#[derive(Encode, Decode)]
+pub struct Header {
+    /// Of the game that recorded it, demos of other versions may not replay right.
+    pub version: String,
+    /// Map file, relative to `assets`.
+    pub map: String,
+    /// Who recorded it, and so sent everything recorded as sent.
+    pub local: SocketAddr,
+    /// Everyone playing when the recording started, with where they were.
+    pub players: Vec<(SocketAddr, [f64; 3], Team)>,
+}
+
+/// Keys and buttons shown while replaying, with how they're shown.
+const INPUTS: [(Option<KeyCode>, &str); 9] = [
+    (Some(KeyCode::W), "W"),
+    (Some(KeyCode::A), "A"),
+    (Some(KeyCode::S), "S"),
+    (Some(KeyCode::D), "D"),
+    (Some(KeyCode::Space), "Jump"),
+    (Some(KeyCode::LeftControl), "Crouch"),
+    (Some(KeyCode::LeftShift), "Walk"),
+    (Some(KeyCode::R), "Reload"),
+    // The left mouse button
+    (None, "Fire"),
+];
+
+/// What the recording player held during a tick, a bit for each of `INPUTS`.
+#[derive(Clone, Copy, Default, Encode, Decode)]
+pub struct Input {
+    held: u16,
+}
+
+impl Input {
+    pub fn read() -> Self {
+        let mut held = 0;
+        for (bit, (key, _)) in INPUTS.iter().enumerate() {
+            let down = match key {
+                Some(key) => is_key_down(*key),
+                None => is_mouse_button_down(MouseButton::Left),
+            };
+            held |= (down as u16) << bit;
+        }
+        Self { held }
+    }
+
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        INPUTS
+            .iter()
+            .enumerate()
+            .filter(move |(bit, _)| (self.held >> bit) & 1 == 1)
+            .map(|(_, (_, name))| *name)
+    }
+}
+
+#[derive(Encode, Decode)]
+enum Record {
+    /// An encoded `Packet` and whom it came from.
+    Received {
+        src: SocketAddr,
+        packet: Vec<u8>,
+    },
+    /// An encoded `Packet`, once however many it went to.
+    Sent {
+        packet: Vec<u8>,
+    },
+    Input(Input),
+}
+
+#[derive(Encode, Decode)]
+struct Entry {
+    /// Since the entry before, which keeps the time down to a byte most of the time.
+    millis: u32,
+    record: Record,
+}
+
+/// Writes everything that goes through the socket to a demo file, given by `RECORD`.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+    /// Milliseconds from `start` to the last entry.
+    last: u32,
+    last_flush: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &str, header: &Header) -> Self {
+        let mut writer = BufWriter::new(File::create(path).expect("Couldn't create the demo."));
+        encode_into_std_write(header, &mut writer, config::standard()).unwrap();
+        Self {
+            writer,
+            start: Instant::now(),
+            last: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    fn record(&mut self, record: Record) {
+        let now = self.start.elapsed().as_millis() as u32;
+        encode_into_std_write(
+            Entry {
+                millis: now - self.last,
+                record,
+            },
+            &mut self.writer,
+            config::standard(),
+        )
+        .unwrap();
+        self.last = now;
+
+        // There's no telling when the window gets closed, so at most a tick is lost
+        if self.last_flush.elapsed() >= *DURATION_PER_TICK {
+            self.last_flush = Instant::now();
+            self.writer.flush().unwrap();
+        }
+    }
+
+    pub fn received(&mut self, src: SocketAddr, packet: &[u8]) {
+        self.record(Record::Received {
+            src,
+            packet: packet.to_vec(),
+        });
+    }
+
+    pub fn sent(&mut self, packet: &[u8]) {
+        self.record(Record::Sent {
+            packet: packet.to_vec(),
+        });
+    }
+
+    pub fn input(&mut self, input: Input) {
+        self.record(Record::Input(input));
+    }
+}
+
+/// What the playback keys did this frame.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Seek {
+    None,
+    Forward,
+    /// Everything has to be replayed from the start.
+    Back,
+}
+
+/// A demo being replayed. P pauses, the left and right arrows seek and the up and down arrows
+/// change the speed.
+pub struct Demo {
+    pub header: Header,
+    /// Each with its time in seconds from the start.
+    entries: Vec<(f64, Record)>,
+    /// Index of the first entry not yet replayed.
+    next: usize,
+    /// Seconds from the start.
+    pub time: f64,
+    pub speed: f64,
+    pub paused: bool,
+    /// Latest of the recording player.
+    pub input: Input,
+}
+
+impl Demo {
+    /// Loads a demo given by its path, not relative to `assets`. One cut short by the game
+    /// closing mid-write is replayed up to there.
+    pub fn load(path: &str) -> Self {
+        let bytes = read(path).expect("Couldn't read the demo.");
+        let config = config::standard();
+        let (header, mut offset): (Header, _) =
+            decode_from_slice(&bytes, config).expect("Invalid demo.");
+        if header.version != env!("CARGO_PKG_VERSION") {
+            eprintln!(
+                "Warning: {path} was recorded by version {}, replaying it with {}",
+                header.version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+
+        let mut entries = Vec::new();
+        let mut time = 0.0;
+        while offset < bytes.len() {
+            match decode_from_slice::<Entry, _>(&bytes[offset..], config) {
+                Ok((entry, length)) => {
+                    offset += length;
+                    time += entry.millis as f64 / 1000.0;
+                    entries.push((time, entry.record));
+                }
+                Err(e) => {
+                    eprintln!("Warning: {path} is cut short: {e}");
+                    break;
+                }
+            }
+        }
+
+        Self {
+            header,
+            entries,
+            next: 0,
+            time: 0.0,
+            speed: 1.0,
+            paused: false,
+            input: Input::default(),
+        }
+    }
+
+    /// In seconds.
+    pub fn length(&self) -> f64 {
+        self.entries.last().map_or(0.0, |(time, _)| *time)
+    }
+
+    /// The players as they were when the recording started, the recording one included.
+    pub fn players(&self) -> HashMap<SocketAddr, Player> {
+        self.header
+            .players
+            .iter()
+            .map(|(peer_host, position, team)| {
+                (*peer_host, Player::new(DVec3::from_array(*position), *team))
+            })
+            .collect()
+    }
+
+    /// Moves the playback on by `delta` seconds of real time and handles the playback keys.
+    pub fn controls(&mut self, delta: f64) -> Seek {
+        if is_key_pressed(KeyCode::P) {
+            self.paused = !self.paused;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            self.speed = (self.speed * 2.0).min(DEMO_MAX_SPEED);
+        }
+        if is_key_pressed(KeyCode::Down) {
+            self.speed = (self.speed / 2.0).max(DEMO_MIN_SPEED);
+        }
+        if !self.paused {
+            self.time = (self.time + delta * self.speed).min(self.length());
+        }
+
+        let step = is_key_pressed(KeyCode::Right) as i32 - is_key_pressed(KeyCode::Left) as i32;
+        self.seek(self.time + step as f64 * DEMO_SEEK)
+    }
+
+    fn seek(&mut self, time: f64) -> Seek {
+        let time = time.clamp(0.0, self.length());
+        let seek = if time > self.time {
+            Seek::Forward
+        } else if time < self.time {
+            self.next = 0;
+            self.input = Input::default();
+            Seek::Back
+        } else {
+            Seek::None
+        };
+        self.time = time;
+        seek
+    }
+
+    /// Packets due by now, encoded, with whom they came from.
+    pub fn due(&mut self) -> Vec<(SocketAddr, &[u8])> {
+        let mut packets = Vec::new();
+        while let Some((time, record)) = self.entries.get(self.next)
+            && *time <= self.time
+        {
+            self.next += 1;
+            match record {
+                Record::Received { src, packet } => packets.push((*src, packet.as_slice())),
+                Record::Sent { packet } => packets.push((self.header.local, packet.as_slice())),
+                Record::Input(input) => self.input = *input,
+            }
+        }
+        packets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env::temp_dir, thread::sleep, time::Duration};
+
+    #[test]
+    fn replays_what_was_recorded_in_order() {
+        let path = temp_dir().join("librego-demo-test.demo");
+        let path = path.to_str().unwrap();
+        let local = "127.0.0.1:5000".parse().unwrap();
+        let peer = "127.0.0.1:5001".parse().unwrap();
+
+        let mut recorder = Recorder::create(
+            path,
+            &Header {
+                version: env!("CARGO_PKG_VERSION").to_owned(),
+                map: "arena.toml".to_owned(),
+                local,
+                players: vec![(local, [0.0; 3], Team::Red), (peer, [1.0; 3], Team::Blue)],
+            },
+        );
+        recorder.received(peer, &[1, 2, 3]);
+        recorder.input(Input {
+            held: 0b1_0000_0001,
+        });
+        sleep(Duration::from_millis(10));
+        recorder.sent(&[4, 5]);
+        // Written out by dropping it
+        drop(recorder);
+
+        let mut demo = Demo::load(path);
+        assert_eq!(demo.players().len(), 2);
+        assert_eq!(demo.header.map, "arena.toml");
+
+        assert!(demo.length() >= 0.01);
+        demo.time = demo.length();
+        assert_eq!(
+            demo.due(),
+            [(peer, [1, 2, 3].as_slice()), (local, [4, 5].as_slice())]
+        );
+        assert_eq!(demo.input.names().collect::<Vec<_>>(), ["W", "Fire"]);
+        assert!(demo.due().is_empty());
+
+        // Going back replays from the start
+        assert!(demo.seek(-1.0) == Seek::Back);
+        demo.time = demo.length();
+        assert_eq!(demo.due().len(), 2);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{
+    consts::*,
+    player::{Shot, Surface},
+};
+use macroquad::prelude::*;
+use std::time::Instant;
+
+struct Tracer {
+    from: DVec3,
+    to: DVec3,
+    timestamp: Instant,
+}
+
+struct Impact {
+    position: DVec3,
+    surface: Surface,
+    timestamp: Instant,
+}
+
+struct Flash {
+    position: DVec3,
+    timestamp: Instant,
+}
+
+/// Short-lived visuals left behind by shots.
+pub struct Effects {
+    tracers: Vec<Tracer>,
+    impacts: Vec<Impact>,
+    flashes: Vec<Flash>,
+}
+
+impl Effects {
+    pub fn new() -> Self {
+        Self {
+            tracers: Vec::new(),
+            impacts: Vec::new(),
+            flashes: Vec::new(),
+        }
+    }
+
+    /// `muzzle` is where the tracers start, which for the local player is off the camera.
+    pub fn shot(&mut self, shot: &Shot, muzzle: DVec3) {
+        let timestamp = Instant::now();
+
+        self.flashes.push(Flash {
+            position: muzzle,
+            timestamp,
+        });
+
+        for &(end, surface) in &shot.pellets {
+            let end = DVec3::from_array(end);
+            self.tracers.push(Tracer {
+                from: muzzle,
+                to: end,
+                timestamp,
+            });
+            if surface != Surface::Air {
+                self.impacts.push(Impact {
+                    position: end,
+                    surface,
+                    timestamp,
+                });
+            }
+        }
+
+        if self.impacts.len() > MAX_IMPACTS {
+            self.impacts.drain(..self.impacts.len() - MAX_IMPACTS);
+        }
+    }
+
+    /// Must be called within the 3D pass.
+    pub fn draw(&mut self) {
+        self.tracers
+            .retain(|tracer| tracer.timestamp.elapsed() < TRACER_DURATION);
+        self.impacts
+            .retain(|impact| impact.timestamp.elapsed() < IMPACT_DURATION);
+        self.flashes
+            .retain(|flash| flash.timestamp.elapsed() < FLASH_DURATION);
+
+        for tracer in &self.tracers {
+            let fade =
+                1.0 - tracer.timestamp.elapsed().as_secs_f32() / TRACER_DURATION.as_secs_f32();
+            draw_line_3d(
+                tracer.from.as_vec3(),
+                tracer.to.as_vec3(),
+                Color {
+                    a: fade,
+                    ..TRACER_COLOR
+                },
+            );
+        }
+
+        for impact in &self.impacts {
+            draw_cube(
+                impact.position.as_vec3(),
+                Vec3::splat(IMPACT_SIZE),
+                None,
+                match impact.surface {
+                    Surface::Player => RED,
+                    _ => LIGHTGRAY,
+                },
+            );
+        }
+
+        for flash in &self.flashes {
+            draw_sphere(flash.position.as_vec3(), FLASH_SIZE, None, YELLOW);
+        }
+    }
+}
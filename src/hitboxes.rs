@@ -0,0 +1,180 @@
+use crate::{
+    consts::*,
+    model::{Part, Pose, bones},
+    player::Player,
+    round::Team,
+};
+use macroquad::prelude::*;
+use parry3d_f64::{
+    bounding_volume::Aabb,
+    math::{Point, Vector},
+    partitioning::Qbvh,
+    query::{Ray, RayCast, visitors::RayIntersectionsVisitor},
+    shape::Cuboid,
+};
+use std::{collections::HashMap, net::SocketAddr};
+
+/// Where an enemy was at one of its recent ticks.
+struct Sample {
+    owner: SocketAddr,
+    position: DVec3,
+    yaw: f64,
+    pitch: f64,
+    pose: Pose,
+}
+
+/// Every enemy at every recent tick in a bounding volume hierarchy, so that a pellet only poses
+/// and tests the bones of the few samples it passes near.
+pub struct Hitboxes {
+    qbvh: Qbvh<u32>,
+    samples: Vec<Sample>,
+    time: f64,
+}
+
+impl Hitboxes {
+    /// Of the peers that `team` can shoot, posed at `time`.
+    pub fn new(peers: &HashMap<SocketAddr, Player>, team: Team, time: f64) -> Self {
+        let mut samples = Vec::new();
+        for (peer_host, peer) in peers {
+            if peer.killed || peer.protected || peer.team == team {
+                continue;
+            }
+
+            samples.extend(peer.ticks.iter().flatten().map(|&position| Sample {
+                owner: *peer_host,
+                position,
+                yaw: peer.yaw,
+                pitch: peer.pitch,
+                pose: peer.pose,
+            }));
+        }
+
+        let mut qbvh = Qbvh::new();
+        qbvh.clear_and_rebuild(
+            samples.iter().enumerate().map(|(index, sample)| {
+                let center = Point::new(sample.position.x, sample.position.y, sample.position.z);
+                let bounds = Vector::new(HITBOX_BOUNDS.x, HITBOX_BOUNDS.y, HITBOX_BOUNDS.z);
+                (index as u32, Aabb::from_half_extents(center, bounds))
+            }),
+            0.0,
+        );
+
+        Self {
+            qbvh,
+            samples,
+            time,
+        }
+    }
+
+    /// Nearest bone along `ray` within `max_time_of_impact`, with whom and what it belongs to.
+    pub fn cast_ray(&self, ray: &Ray, max_time_of_impact: f64) -> Option<(f64, SocketAddr, Part)> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut candidates = Vec::new();
+        let mut collect = |index: &u32| {
+            candidates.push(*index as usize);
+            true
+        };
+        let mut visitor = RayIntersectionsVisitor::new(ray, max_time_of_impact, &mut collect);
+        self.qbvh.traverse_depth_first(&mut visitor);
+
+        let mut nearest = None;
+        let mut max_time_of_impact = max_time_of_impact;
+        for index in candidates {
+            let sample = &self.samples[index];
+            for bone in bones(
+                sample.position,
+                sample.yaw,
+                sample.pitch,
+                sample.pose,
+                self.time,
+            ) {
+                if bone.part == Part::Gun {
+                    continue;
+                }
+
+                let half_extents = bone.half_extents;
+                let cuboid =
+                    Cuboid::new(Vector::new(half_extents.x, half_extents.y, half_extents.z));
+                if let Some(toi) = cuboid.cast_ray(&bone.isometry(), ray, max_time_of_impact, true)
+                {
+                    max_time_of_impact = toi;
+                    nearest = Some((toi, sample.owner, bone.part));
+                }
+            }
+        }
+        nearest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::rand::{Rng, SeedableRng, rngs::StdRng};
+    use std::f64::consts::TAU;
+
+    #[test]
+    fn finds_the_same_bones_as_testing_every_one() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let peers = (0..8)
+            .map(|i| {
+                let team = if i % 4 == 0 { Team::Red } else { Team::Blue };
+                let mut peer = Player::new(DVec3::ZERO, team);
+                peer.yaw = rng.random_range(0.0..TAU);
+                peer.pose = Pose::Run;
+                peer.ticks = (0..4)
+                    .map(|_| {
+                        Some(dvec3(
+                            rng.random_range(-3.0..3.0),
+                            PLAYER_SIZE.y,
+                            rng.random_range(-3.0..3.0),
+                        ))
+                    })
+                    .collect();
+                (format!("127.0.0.1:{}", 1000 + i).parse().unwrap(), peer)
+            })
+            .collect::<HashMap<SocketAddr, Player>>();
+        let hitboxes = Hitboxes::new(&peers, Team::Red, 0.0);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let angle = rng.random_range(0.0..TAU);
+            let ray = Ray::new(
+                Point::new(0.0, rng.random_range(0.0..1.0), 0.0),
+                Vector::new(angle.cos(), rng.random_range(-0.2..0.2), angle.sin()),
+            );
+
+            let mut expected = None;
+            let mut max_time_of_impact = BULLET_RANGE;
+            for (peer_host, peer) in &peers {
+                if peer.team == Team::Red {
+                    continue;
+                }
+                for position in peer.ticks.iter().flatten() {
+                    for bone in bones(*position, peer.yaw, peer.pitch, peer.pose, 0.0) {
+                        let half_extents = bone.half_extents;
+                        let cuboid = Cuboid::new(Vector::new(
+                            half_extents.x,
+                            half_extents.y,
+                            half_extents.z,
+                        ));
+                        if bone.part != Part::Gun
+                            && let Some(toi) =
+                                cuboid.cast_ray(&bone.isometry(), &ray, max_time_of_impact, true)
+                        {
+                            max_time_of_impact = toi;
+                            expected = Some((*peer_host, bone.part));
+                        }
+                    }
+                }
+            }
+
+            let found = hitboxes.cast_ray(&ray, BULLET_RANGE);
+            assert_eq!(found.map(|(_, owner, part)| (owner, part)), expected);
+            hits += found.is_some() as usize;
+        }
+        assert!(hits > 100);
+    }
+}
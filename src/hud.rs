@@ -0,0 +1,301 @@
+use crate::{
+    consts::*,
+    demo::Demo,
+    player::Player,
+    round::{Phase, Round, Team},
+    spectator::Spectator,
+    weapon::Weapon,
+};
+use macroquad::prelude::*;
+use std::net::SocketAddr;
+
+#[derive(Clone, Copy)]
+pub enum Align {
+    Start,
+    Middle,
+    End,
+}
+
+/// Where on the screen a widget sits, along x and along y.
+#[derive(Clone, Copy)]
+pub struct Anchor {
+    pub x: Align,
+    pub y: Align,
+}
+
+impl Anchor {
+    pub const TOP_LEFT: Self = Self {
+        x: Align::Start,
+        y: Align::Start,
+    };
+    pub const TOP: Self = Self {
+        x: Align::Middle,
+        y: Align::Start,
+    };
+    pub const TOP_RIGHT: Self = Self {
+        x: Align::End,
+        y: Align::Start,
+    };
+    pub const CENTER: Self = Self {
+        x: Align::Middle,
+        y: Align::Middle,
+    };
+    pub const BOTTOM: Self = Self {
+        x: Align::Middle,
+        y: Align::End,
+    };
+}
+
+/// Places HUD widgets for the current frame. Sizes and offsets are given in pixels at
+/// `DEFAULT_SCREEN_SIZE` and scaled with the screen height, so the HUD takes up the same share
+/// of the screen at any resolution, DPI or window size.
+pub struct Layout {
+    pub size: Vec2,
+    pub scale: f32,
+}
+
+impl Layout {
+    /// Must be made every frame so that resizing the window is picked up.
+    pub fn new() -> Self {
+        let size = vec2(screen_width(), screen_height());
+        Self {
+            size,
+            scale: size.y / DEFAULT_SCREEN_SIZE.y * HUD_SCALE,
+        }
+    }
+
+    pub fn center(&self) -> Vec2 {
+        self.size / 2.0
+    }
+
+    /// Top-left corner of a box of `size` screen pixels at `anchor`, moved by `offset` towards
+    /// the inside of the screen, or right and down when in the middle.
+    pub fn place(&self, anchor: Anchor, offset: Vec2, size: Vec2) -> Vec2 {
+        let offset = offset * self.scale;
+        let along = |align, screen: f32, size: f32, offset: f32| match align {
+            Align::Start => offset,
+            Align::Middle => (screen - size) / 2.0 + offset,
+            Align::End => screen - size - offset,
+        };
+        vec2(
+            along(anchor.x, self.size.x, size.x, offset.x),
+            along(anchor.y, self.size.y, size.y, offset.y),
+        )
+    }
+
+    /// Draws `text` aligned to `anchor` and returns the box it takes.
+    pub fn text(
+        &self,
+        text: &str,
+        anchor: Anchor,
+        offset: Vec2,
+        font_size: u16,
+        color: Color,
+    ) -> Rect {
+        // Measured and drawn at the same size
+        let font_size = (font_size as f32 * self.scale).round() as u16;
+        let measured = measure_text(text, None, font_size, 1.0);
+        let position = self.place(anchor, offset, vec2(measured.width, measured.height));
+        draw_text(
+            text,
+            position.x,
+            position.y + measured.offset_y,
+            font_size as f32,
+            color,
+        );
+        Rect::new(position.x, position.y, measured.width, measured.height)
+    }
+}
+
+/// Weapon name, ammo and, while reloading, a bar under it.
+pub fn draw_ammo(layout: &Layout, player: &Player, weapons: &[Weapon]) {
+    let weapon = &weapons[player.weapon];
+    let text = layout.text(
+        &format!(
+            "{} {}/{} | {}",
+            weapon.name,
+            weapon.magazine - player.slot().bullets_since_last_reload,
+            weapon.magazine,
+            player.slot().reserve,
+        ),
+        Anchor::TOP_RIGHT,
+        vec2(HUD_MARGIN, HUD_MARGIN),
+        BULLETS_FONT_SIZE,
+        WHITE,
+    );
+
+    if let Some(progress) = player.reload_progress(weapons) {
+        let bar = Rect::new(
+            text.x,
+            text.bottom() + text.h * RELOAD_BAR_HEIGHT,
+            text.w,
+            text.h * RELOAD_BAR_HEIGHT,
+        );
+        draw_rectangle(bar.x, bar.y, bar.w, bar.h, DARKGRAY);
+        draw_rectangle(bar.x, bar.y, bar.w * progress as f32, bar.h, WHITE);
+    }
+}
+
+/// Score, round number and timer at the top, the phase banner and, unless only spectating,
+/// health, money and spawn protection at the bottom.
+pub fn draw_round(layout: &Layout, round: &Round, player: Option<&Player>) {
+    let remaining = round.remaining().as_secs();
+
+    let score = layout.text(
+        &format!(
+            "{}  Round {}  {}",
+            round.score[Team::Red.index()],
+            round.number,
+            round.score[Team::Blue.index()],
+        ),
+        Anchor::TOP,
+        vec2(0.0, HUD_MARGIN),
+        ROUND_FONT_SIZE,
+        WHITE,
+    );
+    layout.text(
+        &format!("{}:{:02}", remaining / 60, remaining % 60),
+        Anchor::TOP,
+        vec2(0.0, score.bottom() / layout.scale + HUD_SPACING),
+        ROUND_FONT_SIZE,
+        WHITE,
+    );
+
+    let banner = match round.phase {
+        Phase::Buy => Some(("Buy phase, press B to buy".to_owned(), WHITE)),
+        Phase::Live => None,
+        Phase::Over(Some(team)) => Some((format!("{team:?} wins the round"), team.color())),
+        Phase::Over(None) => Some(("Draw".to_owned(), WHITE)),
+    };
+    if let Some((banner, color)) = banner {
+        layout.text(
+            &banner,
+            Anchor::CENTER,
+            vec2(0.0, -DEFAULT_SCREEN_SIZE.y / 4.0),
+            ROUND_FONT_SIZE,
+            color,
+        );
+    }
+
+    let Some(player) = player else {
+        return;
+    };
+    let health = layout.text(
+        &format!("{} HP  ${}", player.health.max(0.0).ceil(), player.money),
+        Anchor::BOTTOM,
+        vec2(0.0, HUD_MARGIN),
+        ROUND_FONT_SIZE,
+        player.team.color(),
+    );
+    if player.protected {
+        layout.text(
+            "Spawn protection",
+            Anchor::BOTTOM,
+            vec2(0.0, (layout.size.y - health.y) / layout.scale + HUD_SPACING),
+            ROUND_FONT_SIZE,
+            WHITE,
+        );
+    }
+}
+
+/// Name of a map event the player set off, under the middle of the screen.
+pub fn draw_notice(layout: &Layout, name: &str) {
+    layout.text(
+        name,
+        Anchor::CENTER,
+        vec2(0.0, DEFAULT_SCREEN_SIZE.y / 4.0),
+        ROUND_FONT_SIZE,
+        WHITE,
+    );
+}
+
+/// Who is being watched and how, with the controls under it.
+pub fn draw_spectating(layout: &Layout, spectator: &Spectator, followed: Option<&Player>) {
+    let (text, color) = match (spectator.target, followed) {
+        (Some(target), Some(followed)) => (
+            format!("Spectating {target}, {}", spectator.mode.name()),
+            followed.team.color(),
+        ),
+        _ => ("Spectating, free camera".to_owned(), WHITE),
+    };
+    let top = DEFAULT_SCREEN_SIZE.y / 3.0;
+    layout.text(
+        &text,
+        Anchor::CENTER,
+        vec2(0.0, top),
+        ROUND_FONT_SIZE,
+        color,
+    );
+    layout.text(
+        "Click to switch players, Space to switch views",
+        Anchor::CENTER,
+        vec2(0.0, top + ROUND_FONT_SIZE as f32 + HUD_SPACING),
+        ROUND_FONT_SIZE,
+        LIGHTGRAY,
+    );
+}
+
+/// Whose eyes the killcam replays through.
+pub fn draw_killcam(layout: &Layout, killer: SocketAddr, team: Option<Team>) {
+    layout.text(
+        &format!("Killcam, killed by {killer}"),
+        Anchor::CENTER,
+        vec2(0.0, DEFAULT_SCREEN_SIZE.y / 3.0),
+        ROUND_FONT_SIZE,
+        team.map_or(WHITE, Team::color),
+    );
+}
+
+/// Playback time, speed and the controls at the top right, with what the recording player held
+/// under them.
+pub fn draw_demo(layout: &Layout, demo: &Demo) {
+    let clock = |seconds: f64| format!("{}:{:02}", seconds as u64 / 60, seconds as u64 % 60);
+    let lines = [
+        format!(
+            "{} / {}  x{}{}",
+            clock(demo.time),
+            clock(demo.length()),
+            demo.speed,
+            if demo.paused { "  paused" } else { "" }
+        ),
+        "P pause, arrows seek and change speed".to_owned(),
+        demo.input.names().collect::<Vec<_>>().join(" "),
+    ];
+    let line = ROUND_FONT_SIZE as f32 + HUD_SPACING;
+    for (index, text) in lines.iter().enumerate() {
+        layout.text(
+            text,
+            Anchor::TOP_RIGHT,
+            vec2(HUD_MARGIN, HUD_MARGIN + index as f32 * line),
+            ROUND_FONT_SIZE,
+            if index == 1 { LIGHTGRAY } else { WHITE },
+        );
+    }
+}
+
+/// One line per weapon, grayed out when it can't be afforded.
+pub fn draw_buy_menu(layout: &Layout, player: &Player, weapons: &[Weapon]) {
+    let line = ROUND_FONT_SIZE as f32 + HUD_SPACING;
+    let top = -line * weapons.len() as f32 / 2.0;
+
+    for (index, weapon) in weapons.iter().enumerate() {
+        let owned = player.slots.iter().any(|slot| slot.weapon == index);
+        layout.text(
+            &format!(
+                "{} {} ${}{}",
+                index + 1,
+                weapon.name,
+                weapon.price,
+                if owned { " (owned)" } else { "" }
+            ),
+            Anchor::CENTER,
+            vec2(0.0, top + index as f32 * line),
+            ROUND_FONT_SIZE,
+            if owned || weapon.price <= player.money {
+                WHITE
+            } else {
+                GRAY
+            },
+        );
+    }
+}
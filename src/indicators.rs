@@ -0,0 +1,94 @@
+use crate::{consts::*, hud::Layout, player::Player};
+use macroquad::prelude::*;
+use std::time::Instant;
+
+struct DamageArc {
+    /// Where the attacker fired from.
+    origin: DVec3,
+    timestamp: Instant,
+}
+
+/// Feedback around the crosshair: a marker when our shots hit and arcs pointing at whoever hurt
+/// us.
+pub struct Indicators {
+    /// When we last hit someone and whether it was a kill.
+    hit: Option<(Instant, bool)>,
+    damage: Vec<DamageArc>,
+}
+
+impl Indicators {
+    pub fn new() -> Self {
+        Self {
+            hit: None,
+            damage: Vec::new(),
+        }
+    }
+
+    pub fn hit(&mut self, kill: bool) {
+        self.hit = Some((Instant::now(), kill));
+    }
+
+    pub fn damage(&mut self, origin: DVec3) {
+        self.damage.push(DamageArc {
+            origin,
+            timestamp: Instant::now(),
+        });
+    }
+
+    pub fn draw(&mut self, layout: &Layout, player: &Player) {
+        let center = layout.center();
+
+        if let Some((timestamp, kill)) = self.hit {
+            let elapsed = timestamp.elapsed();
+            if elapsed < HIT_MARKER_DURATION {
+                let (size, color) = if kill {
+                    (HIT_MARKER_SIZE * KILL_MARKER_SCALE, KILL_MARKER_COLOR)
+                } else {
+                    (HIT_MARKER_SIZE, HIT_MARKER_COLOR)
+                };
+                let color = Color {
+                    a: 1.0 - elapsed.as_secs_f32() / HIT_MARKER_DURATION.as_secs_f32(),
+                    ..color
+                };
+                for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                    let direction = vec2(x, y).normalize();
+                    let from = center + direction * HIT_MARKER_GAP * layout.scale;
+                    let to = from + direction * size * layout.scale;
+                    draw_line(
+                        from.x,
+                        from.y,
+                        to.x,
+                        to.y,
+                        HIT_MARKER_THICKNESS * layout.scale,
+                        color,
+                    );
+                }
+            } else {
+                self.hit = None;
+            }
+        }
+
+        self.damage
+            .retain(|arc| arc.timestamp.elapsed() < DAMAGE_ARC_DURATION);
+        for arc in &self.damage {
+            let offset = arc.origin - player.position;
+            // Clockwise from straight ahead, which is up on the screen
+            let angle = (offset.z.atan2(offset.x) - player.yaw).to_degrees() as f32;
+            let fade =
+                1.0 - arc.timestamp.elapsed().as_secs_f32() / DAMAGE_ARC_DURATION.as_secs_f32();
+            draw_arc(
+                center.x,
+                center.y,
+                DAMAGE_ARC_SIDES,
+                layout.size.y * DAMAGE_ARC_RADIUS,
+                angle - 90.0 - DAMAGE_ARC_SPAN / 2.0,
+                DAMAGE_ARC_THICKNESS * layout.scale,
+                DAMAGE_ARC_SPAN,
+                Color {
+                    a: fade,
+                    ..DAMAGE_ARC_COLOR
+                },
+            );
+        }
+    }
+}
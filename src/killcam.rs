@@ -0,0 +1,280 @@
+use crate::{
+    consts::*,
+    model::Pose,
+    player::{Player, Shot},
+    render::View,
+    round::Team,
+};
+use macroquad::prelude::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// What is replicated of a player, enough to draw it and look through its eyes.
+#[derive(Clone, Copy)]
+struct State {
+    position: DVec3,
+    yaw: f64,
+    pitch: f64,
+    pose: Pose,
+    team: Team,
+}
+
+impl State {
+    fn of(player: &Player) -> Self {
+        Self {
+            position: player.position,
+            yaw: player.yaw,
+            pitch: player.pitch,
+            pose: player.pose,
+            team: player.team,
+        }
+    }
+
+    fn player(self) -> Player {
+        let mut player = Player::new(self.position, self.team);
+        player.yaw = self.yaw;
+        player.pitch = self.pitch;
+        player.pose = self.pose;
+        player.killed = self.pose == Pose::Death;
+        player.orient();
+        player
+    }
+}
+
+/// Everyone at one tick, the local player included.
+struct Frame {
+    timestamp: Instant,
+    players: Vec<(SocketAddr, State)>,
+}
+
+/// The last `KILLCAM_DURATION` of the match, kept to be replayed on death.
+pub struct History {
+    frames: VecDeque<Frame>,
+    /// With when they were fired and by whom.
+    shots: VecDeque<(Instant, SocketAddr, Shot)>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+            shots: VecDeque::new(),
+        }
+    }
+
+    fn forget(&mut self) {
+        let Some(start) = Instant::now().checked_sub(KILLCAM_DURATION) else {
+            return;
+        };
+        while self
+            .frames
+            .front()
+            .is_some_and(|frame| frame.timestamp < start)
+        {
+            self.frames.pop_front();
+        }
+        while self
+            .shots
+            .front()
+            .is_some_and(|(timestamp, _, _)| *timestamp < start)
+        {
+            self.shots.pop_front();
+        }
+    }
+
+    /// Takes in everyone once a tick, however often it's called.
+    pub fn record(
+        &mut self,
+        peers: &HashMap<SocketAddr, Player>,
+        local_addr: SocketAddr,
+        player: &Player,
+    ) {
+        if self
+            .frames
+            .back()
+            .is_some_and(|frame| frame.timestamp.elapsed() < *DURATION_PER_TICK)
+        {
+            return;
+        }
+
+        self.forget();
+        self.frames.push_back(Frame {
+            timestamp: Instant::now(),
+            players: peers
+                .iter()
+                .map(|(peer_host, peer)| (*peer_host, State::of(peer)))
+                .chain([(local_addr, State::of(player))])
+                .collect(),
+        });
+    }
+
+    pub fn shot(&mut self, shooter: SocketAddr, shot: &Shot) {
+        self.shots
+            .push_back((Instant::now(), shooter, shot.clone()));
+    }
+
+    /// The history as `killer` saw it, `None` if it wasn't around for any of it.
+    pub fn killcam(&self, killer: SocketAddr) -> Option<Killcam> {
+        let start = self
+            .frames
+            .iter()
+            .find(|frame| frame.players.iter().any(|(addr, _)| *addr == killer))?
+            .timestamp;
+
+        Some(Killcam {
+            killer,
+            frames: self
+                .frames
+                .iter()
+                .filter(|frame| frame.timestamp >= start)
+                .map(|frame| {
+                    (
+                        frame.timestamp - start,
+                        frame.players.iter().copied().collect(),
+                    )
+                })
+                .collect(),
+            shots: self
+                .shots
+                .iter()
+                .filter(|(timestamp, _, _)| *timestamp >= start)
+                .map(|(timestamp, shooter, shot)| (*timestamp - start, *shooter, shot.clone()))
+                .collect(),
+            next_shot: 0,
+            start: Instant::now(),
+        })
+    }
+}
+
+/// The last moments before a death replayed through the eyes of the killer.
+pub struct Killcam {
+    pub killer: SocketAddr,
+    /// Everyone by when they were there from the start of the killcam.
+    frames: Vec<(Duration, HashMap<SocketAddr, State>)>,
+    shots: Vec<(Duration, SocketAddr, Shot)>,
+    /// Index of the first shot not yet replayed.
+    next_shot: usize,
+    /// When the replay started.
+    start: Instant,
+}
+
+impl Killcam {
+    pub fn finished(&self) -> bool {
+        self.frames
+            .last()
+            .is_none_or(|(offset, _)| self.start.elapsed() > *offset)
+    }
+
+    /// The frame to show now, which holds on the last one once finished.
+    fn frame(&self) -> &HashMap<SocketAddr, State> {
+        let elapsed = self.start.elapsed();
+        let index = self
+            .frames
+            .partition_point(|(offset, _)| *offset <= elapsed)
+            .max(1);
+        &self.frames[index - 1].1
+    }
+
+    pub fn killer_team(&self) -> Option<Team> {
+        Some(self.frame().get(&self.killer)?.team)
+    }
+
+    /// Through the eyes of the killer, or where they last were if they left.
+    pub fn view(&self) -> View {
+        let killer = self
+            .frames
+            .iter()
+            .rev()
+            .filter(|(offset, _)| *offset <= self.start.elapsed())
+            .chain(self.frames.first())
+            .find_map(|(_, players)| players.get(&self.killer))
+            .unwrap();
+        let killer = killer.player();
+        View::new(killer.eye(), killer.yaw, killer.pitch)
+    }
+
+    /// Everyone but the killer as they were, to be drawn instead of the peers.
+    pub fn players(&self) -> Vec<Player> {
+        self.frame()
+            .iter()
+            .filter(|(addr, _)| **addr != self.killer)
+            .map(|(_, state)| state.player())
+            .collect()
+    }
+
+    /// Shots fired since the last call, with who fired them.
+    pub fn shots(&mut self) -> Vec<(SocketAddr, Shot)> {
+        let elapsed = self.start.elapsed();
+        let mut shots = Vec::new();
+        while let Some((offset, shooter, shot)) = self.shots.get(self.next_shot)
+            && *offset <= elapsed
+        {
+            self.next_shot += 1;
+            shots.push((*shooter, shot.clone()));
+        }
+        shots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shot(x: f64) -> Shot {
+        Shot {
+            origin: [x, 0.0, 0.0],
+            pellets: Vec::new(),
+            hits: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn replays_from_when_the_killer_showed_up() {
+        let local = "127.0.0.1:5000".parse().unwrap();
+        let killer = "127.0.0.1:5001".parse().unwrap();
+        let player = Player::new(DVec3::ZERO, Team::Red);
+        let now = Instant::now();
+        let at = |millis| now - Duration::from_millis(millis);
+
+        let mut history = History::new();
+        for (millis, killer_there) in [(300, false), (200, true), (100, true)] {
+            let mut players = vec![(local, State::of(&player))];
+            if killer_there {
+                let mut peer = Player::new(dvec3(millis as f64, 0.0, 0.0), Team::Blue);
+                peer.yaw = 1.0;
+                players.push((killer, State::of(&peer)));
+            }
+            history.frames.push_back(Frame {
+                timestamp: at(millis),
+                players,
+            });
+        }
+        history.shots.push_back((at(250), local, shot(1.0)));
+        history.shots.push_back((at(150), killer, shot(2.0)));
+
+        assert_eq!(history.killcam(local).unwrap().frames.len(), 3);
+        assert!(history.killcam("127.0.0.1:5002".parse().unwrap()).is_none());
+
+        let mut killcam = history.killcam(killer).unwrap();
+        assert_eq!(killcam.frames.len(), 2);
+        assert_eq!(killcam.killer_team(), Some(Team::Blue));
+        // Starting where the killer first was, looking how they did
+        let view = killcam.view();
+        assert!((view.front.z.atan2(view.front.x) - 1.0).abs() < 1e-9);
+        assert!(view.eye.x > 150.0);
+        assert_eq!(killcam.players().len(), 1);
+        assert!(!killcam.finished());
+
+        // Only the killer's shot came after they showed up
+        killcam.start = now - Duration::from_secs(1);
+        let shots = killcam.shots();
+        assert_eq!(shots.len(), 1);
+        assert_eq!(shots[0].0, killer);
+        assert!(killcam.shots().is_empty());
+        assert!(killcam.finished());
+        assert!(killcam.view().eye.x < 150.0);
+    }
+}
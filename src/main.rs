@@ -1,39 +1,85 @@
+mod audio;
+#[cfg(test)]
+mod benches;
+mod bot;
 mod consts;
+mod crosshair;
+mod demo;
+mod effects;
+mod hitboxes;
+mod hud;
+mod indicators;
+mod killcam;
+mod map;
+mod model;
+mod movement;
+mod nav;
 mod player;
+mod radar;
+mod render;
+mod round;
+mod spectator;
+mod weapon;
+mod window;
 
-use ::rand::{Rng, SeedableRng, rngs::StdRng};
-use bincode::{Decode, Encode, config, decode_from_slice, encode_into_slice};
+use ::rand::{SeedableRng, rngs::StdRng};
+use audio::{Cue, Sounds};
+use bincode::{Decode, Encode, config, decode_from_slice, encode_to_vec};
+use bot::{Bot, Skill};
 use consts::*;
-use macroquad::{audio::load_sound, prelude::*};
-use parry3d_f64::{
-    math::{Isometry, Vector},
-    shape::{Compound, Cuboid, SharedShape},
+use crosshair::Crosshair;
+use demo::{Demo, Header, Input, Recorder, Seek};
+use effects::Effects;
+use hud::{
+    Layout, draw_ammo, draw_buy_menu, draw_demo, draw_killcam, draw_notice, draw_round,
+    draw_spectating,
 };
-use player::Player;
+use indicators::Indicators;
+use killcam::{History, Killcam};
+use macroquad::{
+    miniquad::window::{clipboard_get, clipboard_set},
+    prelude::*,
+};
+use map::Map;
+use model::{Pose, Style};
+use movement::Movement;
+use nav::Nav;
+use player::{Controls, Player, Shot};
+use radar::Radar;
+use render::{Lighting, View, draw_sky};
+use round::{Phase, Round, RoundState, Team};
+use spectator::{Mode, Spectator};
 use std::{
-    collections::HashMap,
-    env::vars,
+    collections::{HashMap, HashSet},
+    env::{args, vars},
+    mem,
     net::{SocketAddr, UdpSocket},
-    sync::{Arc, RwLock},
+    sync::{
+        Arc, RwLock,
+        mpsc::{self, Sender},
+    },
     thread::spawn,
     time::Instant,
 };
+use weapon::Weapon;
+use window::WindowSettings;
 
 fn window_conf() -> Conf {
-    Conf {
-        window_title: "librego".to_owned(),
-        fullscreen: true,
-        platform: miniquad::conf::Platform {
-            linux_backend: miniquad::conf::LinuxBackend::WaylandWithX11Fallback,
-            ..Default::default()
-        },
-        ..Default::default()
-    }
+    WindowSettings::from_env().conf()
 }
 
-#[derive(Encode, Decode)]
+#[derive(Clone, Encode, Decode)]
 struct Peers {
-    peers: Vec<(SocketAddr, [f64; 3])>,
+    peers: Vec<(SocketAddr, [f64; 3], Team)>,
+    /// Who else is to be told about what happens.
+    spectators: Vec<SocketAddr>,
+    /// Team assigned to the player who registered, meaningless for spectators.
+    team: Team,
+    /// Rules of the match being joined.
+    movement: Movement,
+    /// Which of the `parts` packets the answer is split into this is.
+    part: u16,
+    parts: u16,
 }
 
 #[derive(Encode, Decode)]
@@ -41,6 +87,27 @@ struct MoveQuery {
     x: f64,
     y: f64,
     z: f64,
+    yaw: f64,
+    pitch: f64,
+    pose: Pose,
+    weapon: u8,
+    /// Whether the player is under spawn protection.
+    protected: bool,
+}
+
+impl MoveQuery {
+    fn of(player: &Player) -> Self {
+        Self {
+            x: player.position.x,
+            y: player.position.y,
+            z: player.position.z,
+            yaw: player.yaw,
+            pitch: player.pitch,
+            pose: player.pose,
+            weapon: player.weapon as u8,
+            protected: player.protected,
+        }
+    }
 }
 
 #[derive(Encode, Decode)]
@@ -48,14 +115,34 @@ struct RegisterQuery {
     x: f64,
     y: f64,
     z: f64,
+    /// `None` when asking for a team, `Some` when announcing oneself to the other peers.
+    team: Option<Team>,
+    /// Joins without a body, only to be sent what happens.
+    spectator: bool,
+}
+
+#[derive(Encode, Decode)]
+struct Footstep {
+    position: [f64; 3],
+    walking: bool,
+}
+
+/// Damage a player took from the map, such as from falling or kill volumes, sent to everyone so
+/// that they all agree on its health.
+#[derive(Encode, Decode)]
+struct Hurt {
+    damage: f64,
 }
 
 #[derive(Encode, Decode)]
 enum Event {
     MoveQuery(MoveQuery),
     RegisterQuery(RegisterQuery),
-    Killed,
+    Shot(Shot),
+    Footstep(Footstep),
+    Hurt(Hurt),
     Peers(Peers),
+    RoundState(RoundState),
 }
 
 #[derive(Encode, Decode)]
@@ -63,244 +150,924 @@ struct Packet {
     event: Event,
 }
 
-async fn start(
-    mut player: Player,
-    peers: Arc<RwLock<HashMap<SocketAddr, Player>>>,
-    socket: Arc<RwLock<UdpSocket>>,
+/// Spawn of `team` chosen against where the living peers are.
+fn spawn_position(
+    map: &Map,
+    team: Team,
+    swapped: bool,
+    peers: &HashMap<SocketAddr, Player>,
     rng: &mut StdRng,
-) {
-    for _ in 0..8 {
-        set_fullscreen(true);
-        next_frame().await;
+) -> DVec3 {
+    let (mut teammates, mut enemies) = (Vec::new(), Vec::new());
+    for peer in peers.values().filter(|peer| !peer.killed) {
+        if peer.team == team {
+            teammates.push(peer.position);
+        } else {
+            enemies.push(peer.position);
+        }
     }
+    map.spawn_position(team, swapped, &teammates, &enemies, rng)
+}
 
-    let screen_size = vec2(screen_width(), screen_height());
+/// `None` when the packet wouldn't fit in what peers receive.
+fn encode(event: Event) -> Option<Vec<u8>> {
+    let packet = encode_to_vec(Packet { event }, config::standard()).unwrap();
+    if packet.len() > PACKET_SIZE {
+        eprintln!(
+            "Warning: dropping a packet of {} bytes, more than {PACKET_SIZE}",
+            packet.len()
+        );
+        return None;
+    }
+    Some(packet)
+}
 
-    let peers_clone = peers.clone();
-    let socket_clone = socket.clone();
-    let player_clone = player.clone();
+/// Splits the answer to a registration into as many packets as it takes for each to fit in
+/// `PACKET_SIZE`, spectators included.
+fn split_peers(answer: Peers) -> Vec<Vec<u8>> {
+    let encode = |part: &Peers| {
+        encode_to_vec(
+            Packet {
+                event: Event::Peers(part.clone()),
+            },
+            config::standard(),
+        )
+        .unwrap()
+    };
+    // With room for `part` and `parts` to grow from one byte to three each once numbered
+    let fits = |part: &Peers| encode(part).len() + 4 <= PACKET_SIZE;
 
-    spawn(move || {
-        let socket = socket_clone.read().unwrap();
+    let Peers {
+        peers,
+        spectators,
+        team,
+        movement,
+        ..
+    } = answer;
+    let empty = || Peers {
+        peers: Vec::new(),
+        spectators: Vec::new(),
+        team,
+        movement: movement.clone(),
+        part: 0,
+        parts: 0,
+    };
 
-        let config = config::standard();
-        let peers_clone = peers_clone.clone();
-        let player_clone = player_clone.clone();
+    let mut parts = vec![empty()];
+    for peer in peers {
+        let last = parts.last_mut().unwrap();
+        last.peers.push(peer);
+        if !fits(last) {
+            let mut next = empty();
+            next.peers.extend(last.peers.pop());
+            parts.push(next);
+        }
+    }
+    for spectator in spectators {
+        let last = parts.last_mut().unwrap();
+        last.spectators.push(spectator);
+        if !fits(last) {
+            let mut next = empty();
+            next.spectators.extend(last.spectators.pop());
+            parts.push(next);
+        }
+    }
+
+    let count = parts.len() as u16;
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut part)| {
+            part.part = index as u16;
+            part.parts = count;
+            encode(&part)
+        })
+        .collect()
+}
 
-        loop {
-            let mut buf = [0; 100];
-            let (amt, src) = socket.recv_from(&mut buf).unwrap();
+/// Sends an encoded packet to every peer and spectator but the bots.
+fn broadcast(session: &Session, packet: &[u8]) {
+    let socket_read = session.socket.read().unwrap();
+    for peer_host in session.peers.read().unwrap().keys() {
+        if !session.bots.contains(peer_host) {
+            socket_read.send_to(packet, peer_host).unwrap();
+        }
+    }
+    for spectator in session.spectators.read().unwrap().iter() {
+        socket_read.send_to(packet, spectator).unwrap();
+    }
+    if let Some(recorder) = &session.recorder {
+        recorder.write().unwrap().sent(packet);
+    }
+}
 
-            let (packet, _): (Packet, _) = decode_from_slice(&buf[..amt], config).unwrap();
+/// Where the local player sees and hears from, through the killcam and then the spectator
+/// camera once dead.
+fn view(
+    player: &Player,
+    killcam: Option<&Killcam>,
+    spectator: Option<&Spectator>,
+    peers: &HashMap<SocketAddr, Player>,
+    map: &Map,
+) -> View {
+    match (killcam, spectator) {
+        (Some(killcam), _) => killcam.view(),
+        (None, Some(spectator)) => spectator.view(peers, &map.compound),
+        (None, None) => View::of(player),
+    }
+}
 
-            match packet.event {
-                Event::MoveQuery(query) => {
-                    let mut peers_write = peers_clone.write().unwrap();
+#[derive(Clone, Copy, PartialEq)]
+enum Role {
+    /// Started without `SERVER`, keeps the round going.
+    Host,
+    Player,
+    /// Started with `SPECTATE` or `--play-demo`, only watches.
+    Spectator,
+}
 
-                    let peer = peers_write.get_mut(&src).unwrap();
-                    peer.position = peer.position.lerp(dvec3(query.x, query.y, query.z), 0.5);
-                    peer.position.y = query.y;
-                    if peer.ticks.len() > TICKS_PER_SECOND {
-                        peer.ticks.clear()
-                    } else {
-                        peer.ticks.push(Some(peer.position))
-                    }
-                }
-                Event::RegisterQuery(query) => {
-                    let mut peers_write = peers_clone.write().unwrap();
-                    let mut new_peers = (*peers_write)
-                        .clone()
-                        .iter()
-                        .map(|(peer_host, peer)| {
-                            (
-                                peer_host.clone(),
-                                [peer.position.x, peer.position.y, peer.position.z],
-                            )
-                        })
-                        .collect::<Vec<_>>();
-
-                    new_peers.push((
-                        socket.local_addr().unwrap(),
-                        [
-                            player_clone.position.x,
-                            player_clone.position.y,
-                            player_clone.position.z,
-                        ],
-                    ));
-
-                    peers_write.insert(src, Player::new(dvec3(query.x, query.y, query.z)));
-
-                    let mut buf_send = [0; 100];
-
-                    let length = encode_into_slice(
-                        Packet {
-                            event: Event::Peers(Peers { peers: new_peers }),
-                        },
-                        &mut buf_send,
-                        config,
+/// State shared with the thread receiving packets.
+#[derive(Clone)]
+struct Session {
+    peers: Arc<RwLock<HashMap<SocketAddr, Player>>>,
+    /// Not in `peers` since they have no body to draw or shoot.
+    spectators: Arc<RwLock<HashSet<SocketAddr>>>,
+    round: Arc<RwLock<Round>>,
+    socket: Arc<RwLock<UdpSocket>>,
+    role: Role,
+    /// Given by `RECORD`.
+    recorder: Option<Arc<RwLock<Recorder>>>,
+    /// Peers simulated here, which nothing is sent to.
+    bots: HashSet<SocketAddr>,
+}
+
+/// What the host answers registrations with, nothing when replaying a demo.
+struct Answer {
+    /// The host as a peer.
+    local: (SocketAddr, [f64; 3], Team),
+    movement: Movement,
+}
+
+/// Applies a packet from `src` to the shared state and forwards what the main loop handles to
+/// `events`, whether it came over the network or from a demo.
+fn receive(
+    packet: Packet,
+    src: SocketAddr,
+    session: &Session,
+    events: &Sender<(SocketAddr, Event)>,
+    answer: Option<&Answer>,
+) {
+    match packet.event {
+        Event::MoveQuery(query) => {
+            let mut peers_write = session.peers.write().unwrap();
+
+            let Some(peer) = peers_write.get_mut(&src) else {
+                return;
+            };
+            peer.position = peer.position.lerp(dvec3(query.x, query.y, query.z), 0.5);
+            peer.position.y = query.y;
+            peer.weapon = query.weapon as usize;
+            peer.yaw = query.yaw;
+            peer.pitch = query.pitch;
+            peer.protected = query.protected;
+            if !peer.killed {
+                peer.pose = query.pose;
+            }
+            if peer.ticks.len() > TICKS_PER_SECOND {
+                peer.ticks.clear()
+            } else {
+                peer.ticks.push(Some(peer.position))
+            }
+        }
+        Event::RegisterQuery(query) => {
+            let mut peers_write = session.peers.write().unwrap();
+            let position = dvec3(query.x, query.y, query.z);
+
+            if query.spectator {
+                session.spectators.write().unwrap().insert(src);
+            } else if let Some(team) = query.team {
+                peers_write.insert(src, Player::new(position, team));
+            }
+            if query.team.is_some() {
+                return;
+            }
+
+            // Asking the host to join, who is in the peers already when replaying, and again when
+            // the answer got lost
+            let mut new_peers = peers_write
+                .iter()
+                .filter(|(peer_host, _)| **peer_host != src)
+                .map(|(peer_host, peer)| {
+                    (
+                        *peer_host,
+                        [peer.position.x, peer.position.y, peer.position.z],
+                        peer.team,
                     )
+                })
+                .collect::<Vec<_>>();
+
+            if let Some(answer) = answer {
+                new_peers.push(answer.local);
+            }
+
+            // Put the newcomer into the smaller team
+            let reds = new_peers
+                .iter()
+                .filter(|(_, _, team)| *team == Team::Red)
+                .count();
+            let team = if reds * 2 <= new_peers.len() {
+                Team::Red
+            } else {
+                Team::Blue
+            };
+
+            if !query.spectator {
+                peers_write.insert(src, Player::new(position, team));
+            }
+            let Some(answer) = answer else {
+                return;
+            };
+
+            for packet in split_peers(Peers {
+                peers: new_peers,
+                spectators: session
+                    .spectators
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .filter(|&&spectator| spectator != src)
+                    .copied()
+                    .collect(),
+                team,
+                movement: answer.movement.clone(),
+                part: 0,
+                parts: 0,
+            }) {
+                session
+                    .socket
+                    .read()
+                    .unwrap()
+                    .send_to(&packet, src)
                     .unwrap();
-                    let buf_send_filled = &buf_send[..length];
-                    socket.send_to(&buf_send_filled, &src).unwrap();
+                if let Some(recorder) = &session.recorder {
+                    recorder.write().unwrap().sent(&packet);
                 }
-                Event::Killed => {}
-                _ => panic!(),
             }
         }
-    });
+        Event::Shot(shot) => {
+            {
+                let mut peers_write = session.peers.write().unwrap();
+                if let Some(shooter) = peers_write.get_mut(&src) {
+                    shooter.last_bullet_timestamp = Some(Instant::now());
+                }
+                for hit in &shot.hits {
+                    if let Some(peer) = peers_write.get_mut(&hit.victim) {
+                        peer.damage(hit.damage);
+                    }
+                }
+            }
+            events.send((src, Event::Shot(shot))).unwrap();
+        }
+        Event::Footstep(footstep) => {
+            events.send((src, Event::Footstep(footstep))).unwrap();
+        }
+        Event::Hurt(hurt) => {
+            if let Some(peer) = session.peers.write().unwrap().get_mut(&src) {
+                peer.damage(hurt.damage);
+            }
+        }
+        Event::RoundState(state) => {
+            let mut round_write = session.round.write().unwrap();
+            if state.number != round_write.number {
+                new_round(&mut session.peers.write().unwrap());
+            }
+            round_write.apply(state);
+        }
+        // Only expected as the answer to our own registration
+        Event::Peers(_) => {}
+    }
+}
 
-    let compounds = [
-        (
-            dvec3(5.0, 0.1, 1.0),
-            Cuboid::new(Vector::new(1.0, 0.1, 1.0)),
-        ),
-        (
-            dvec3(5.0, 1.8, 1.0),
-            Cuboid::new(Vector::new(1.0, 0.1, 1.0)),
-        ),
-        (
-            dvec3(HALF, 0.0, 0.0),
-            Cuboid::new(Vector::new(0.0, 20.0, HALF)),
-        ),
-        (
-            dvec3(0.0, 0.0, HALF),
-            Cuboid::new(Vector::new(HALF, 20.0, 0.0)),
-        ),
-        (
-            dvec3(0.0, 0.0, -HALF),
-            Cuboid::new(Vector::new(HALF, 20.0, 0.0)),
-        ),
-        (
-            dvec3(-HALF, 0.0, 0.0),
-            Cuboid::new(Vector::new(0.0, 20.0, HALF)),
+/// Sends `event` from `bot` to every peer and spectator that isn't simulated here, and takes it in
+/// here as if it had come from the bot.
+fn bot_broadcast(session: &Session, bot: &Bot, events: &Sender<(SocketAddr, Event)>, event: Event) {
+    let Some(buf_send_filled) = encode(event) else {
+        return;
+    };
+    for peer_host in session.peers.read().unwrap().keys() {
+        if !session.bots.contains(peer_host) {
+            bot.socket.send_to(&buf_send_filled, peer_host).unwrap();
+        }
+    }
+    for spectator in session.spectators.read().unwrap().iter() {
+        bot.socket.send_to(&buf_send_filled, spectator).unwrap();
+    }
+    if let Some(recorder) = &session.recorder {
+        recorder
+            .write()
+            .unwrap()
+            .received(bot.addr, &buf_send_filled);
+    }
+
+    let (packet, _): (Packet, _) = decode_from_slice(&buf_send_filled, config::standard()).unwrap();
+    receive(packet, bot.addr, session, events, None);
+}
+
+/// Brings the peers back to life for a new round.
+fn new_round(peers: &mut HashMap<SocketAddr, Player>) {
+    for peer in peers.values_mut() {
+        peer.killed = false;
+        peer.health = MAX_HEALTH;
+        peer.pose = Pose::Idle;
+        peer.ticks.clear();
+        peer.last_bullet_timestamp = None;
+    }
+}
+
+/// Everything loaded once before the match starts.
+struct Assets {
+    map: Map,
+    weapons: Vec<Weapon>,
+    movement: Movement,
+}
+
+async fn start(
+    mut player: Player,
+    session: Session,
+    assets: Assets,
+    mut demo: Option<Demo>,
+    mut bots: Vec<Bot>,
+    rng: &mut StdRng,
+) {
+    let Session {
+        peers,
+        spectators,
+        round,
+        socket,
+        role,
+        recorder,
+        ..
+    } = &session;
+    let Assets {
+        map,
+        weapons,
+        movement,
+    } = assets;
+
+    // Only needed by bots, and slow to build on big maps
+    let nav = (!bots.is_empty()).then(|| Nav::new(&map.compound, &map.triggers, map.floor_size));
+
+    let mut window = WindowSettings::from_env();
+    for _ in 0..8 {
+        window.apply();
+        next_frame().await;
+        window.update();
+    }
+
+    // Events about the local player, handled by the main loop
+    let (events_sender, events) = mpsc::channel();
+
+    let answer = Answer {
+        local: (
+            socket.read().unwrap().local_addr().unwrap(),
+            player.position.to_array(),
+            player.team,
         ),
-    ];
-
-    let compound = Compound::new(
-        compounds
-            .map(|(position, shape)| {
-                (
-                    Isometry::translation(position.x, position.y, position.z),
-                    SharedShape::new(shape),
-                )
-            })
-            .to_vec(),
-    );
+        movement: movement.clone(),
+    };
+    let session_clone = session.clone();
+    let events_sender_clone = events_sender.clone();
+
+    // Demos stand in for the network
+    if demo.is_none() {
+        spawn(move || {
+            let session = session_clone;
+            let socket = session.socket.read().unwrap();
+            let config = config::standard();
+
+            loop {
+                let mut buf = [0; PACKET_SIZE];
+                let (amt, src) = socket.recv_from(&mut buf).unwrap();
+                if let Some(recorder) = &session.recorder {
+                    recorder.write().unwrap().received(src, &buf[..amt]);
+                }
+
+                let (packet, _): (Packet, _) = decode_from_slice(&buf[..amt], config).unwrap();
+
+                receive(packet, src, &session, &events_sender_clone, Some(&answer));
+            }
+        });
+    }
 
     let mut grabbed = true;
     set_cursor_grab(grabbed);
     show_mouse(false);
 
-    set_pc_assets_folder("assets");
-    let bullet_sound = load_sound("bullet.ogg").await.unwrap();
+    let sounds = Sounds::load("sounds.toml").await;
+    let style = Style::load("model.toml").await;
+    let lighting = Lighting::new();
+    let mut crosshair = Crosshair::load("crosshair.toml").await;
+    let mut radar = Radar::from_env();
+
+    let mut last_round_number = round.read().unwrap().number;
+    let mut last_phase = round.read().unwrap().phase;
+    let mut buying = false;
+    let mut effects = Effects::new();
+    let mut indicators = Indicators::new();
+    // Latest map event the player fired, and when
+    let mut notice: Option<(String, Instant)> = None;
+    // Only while dead or spectating
+    let mut spectator: Option<Spectator> = None;
+    let mut history = History::new();
+    // Only right after being shot dead
+    let mut killcam: Option<Killcam> = None;
+    // Demos start through the eyes of whoever recorded them
+    if let Some(demo) = &demo {
+        let mut following = Spectator::new(View::of(&player));
+        following.mode = Mode::FirstPerson;
+        following.target = Some(demo.header.local);
+        spectator = Some(following);
+    }
+    let local_addr = socket.read().unwrap().local_addr().unwrap();
 
     loop {
         let delta = get_frame_time() as f64;
 
+        window.update();
+        radar.update();
+
         if is_key_pressed(KeyCode::Tab) {
             grabbed = !grabbed;
             set_cursor_grab(grabbed);
             show_mouse(!grabbed);
         }
 
-        let moved = player.movement(&compound);
-        if grabbed {
-            player.look(delta);
+        // Crosshair share codes through the clipboard
+        if is_key_pressed(KeyCode::F6) {
+            clipboard_set(&crosshair.code());
+        }
+        if is_key_pressed(KeyCode::F7) {
+            match clipboard_get().as_deref().and_then(Crosshair::from_code) {
+                Some(shared) => crosshair = shared,
+                None => eprintln!("Warning: the clipboard holds no crosshair code"),
+            }
         }
-        player.bullets(peers.clone(), &bullet_sound, moved, rng);
 
-        clear_background(BLACK);
+        if let Some(demo) = &mut demo {
+            let seek = demo.controls(delta);
+            if seek == Seek::Back {
+                *peers.write().unwrap() = demo.players();
+                *round.write().unwrap() = Round::new();
+                spectators.write().unwrap().clear();
+                history = History::new();
+            }
 
-        set_camera(&Camera3D {
-            position: player.position.as_vec3(),
-            up: player.up.as_vec3(),
-            target: player.position.as_vec3() + player.front.as_vec3(),
-            fovy: FOV,
-            ..Default::default()
-        });
+            let config = config::standard();
+            for (src, packet) in demo.due() {
+                let (packet, _): (Packet, _) = decode_from_slice(packet, config).unwrap();
+                receive(packet, src, &session, &events_sender, None);
+            }
 
-        let peers_clone = peers.clone();
+            // Not the sounds and effects of everything skipped
+            if seek != Seek::None {
+                events.try_iter().for_each(drop);
+                effects = Effects::new();
+            }
+        }
 
+        // Round
         {
-            let peers_read = peers_clone.read().unwrap();
+            let mut round_write = round.write().unwrap();
 
-            for peer in peers_read.values() {
-                draw_cube(
-                    peer.position.as_vec3(),
-                    DVec3::from_slice(PLAYER_SIZE.as_slice()).as_vec3() * 2.0,
-                    None,
-                    if peer.killed { GRAY } else { RED },
-                );
+            if *role == Role::Host {
+                let peers_read = peers.read().unwrap();
+                let mut present = [0; 2];
+                let mut alive = [0; 2];
+                for member in peers_read
+                    .values()
+                    .map(|peer| (peer.team, peer.killed))
+                    .chain([(player.team, player.killed)])
+                {
+                    present[member.0.index()] += 1;
+                    if !member.1 {
+                        alive[member.0.index()] += 1;
+                    }
+                }
+                round_write.update(present, alive);
             }
+
+            if round_write.number != last_round_number {
+                last_round_number = round_write.number;
+                if round_write.number == 1 {
+                    player.money = START_MONEY;
+                }
+                if *role != Role::Spectator {
+                    let position = spawn_position(
+                        &map,
+                        player.team,
+                        round_write.swapped(),
+                        &peers.read().unwrap(),
+                        rng,
+                    );
+                    player.respawn(position, &weapons);
+                }
+                // Everyone else does it as the new round comes in
+                if *role == Role::Host {
+                    let mut peers_write = peers.write().unwrap();
+                    for bot in &bots {
+                        let Some(team) = peers_write.get(&bot.addr).map(|body| body.team) else {
+                            continue;
+                        };
+                        let position =
+                            spawn_position(&map, team, round_write.swapped(), &peers_write, rng);
+                        peers_write
+                            .get_mut(&bot.addr)
+                            .unwrap()
+                            .respawn(position, &weapons);
+                    }
+                    new_round(&mut peers_write);
+                }
+            }
+
+            if round_write.phase != last_phase {
+                last_phase = round_write.phase;
+                // Counted from when the player can first move
+                if round_write.phase == Phase::Live && *role != Role::Spectator {
+                    player.protect();
+                    let mut peers_write = peers.write().unwrap();
+                    for bot in &bots {
+                        if let Some(body) = peers_write.get_mut(&bot.addr) {
+                            body.protect();
+                        }
+                    }
+                }
+                if let Phase::Over(winner) = round_write.phase {
+                    player.earn(if winner == Some(player.team) {
+                        ROUND_WIN_REWARD
+                    } else {
+                        ROUND_LOSS_REWARD
+                    });
+                }
+            }
+
+            if round_write.phase != Phase::Buy {
+                buying = false;
+            }
+        }
+
+        if killcam.as_ref().is_some_and(Killcam::finished) || !player.killed {
+            killcam = None;
+        }
+        if player.killed && killcam.is_none() {
+            // Dead players only get to watch their own team
+            let team = (*role != Role::Spectator).then_some(player.team);
+            spectator
+                .get_or_insert_with(|| Spectator::new(View::of(&player)))
+                .update(&peers.read().unwrap(), team, &map.compound, grabbed, delta);
+        } else {
+            spectator = None;
+        }
+        let listener = view(
+            &player,
+            killcam.as_ref(),
+            spectator.as_ref(),
+            &peers.read().unwrap(),
+            &map,
+        );
+
+        for (src, event) in events.try_iter() {
+            match event {
+                Event::Shot(shot) => {
+                    history.shot(src, &shot);
+                    let origin = DVec3::from_array(shot.origin);
+                    for hit in &shot.hits {
+                        if hit.victim == local_addr {
+                            indicators.damage(origin);
+                            if player.damage(hit.damage) {
+                                sounds.play(Cue::Death, 1.0, 0.0);
+                                killcam = history.killcam(src);
+                            }
+                        }
+                    }
+                    // The killcam shows the shots of the past instead
+                    if killcam.is_none() {
+                        effects.shot(&shot, origin);
+                        sounds.play_at(Cue::Gunshot, &listener, &map.compound, origin, 1.0);
+                    }
+                }
+                Event::Footstep(footstep) if killcam.is_none() => {
+                    sounds.play_at(
+                        Cue::Footstep,
+                        &listener,
+                        &map.compound,
+                        DVec3::from_array(footstep.position),
+                        if footstep.walking {
+                            WALKING_FOOTSTEP_VOLUME
+                        } else {
+                            FOOTSTEP_VOLUME
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+        history.record(&peers.read().unwrap(), local_addr, &player);
+
+        let phase = round.read().unwrap().phase;
+        let frozen = player.killed || phase == Phase::Buy;
+
+        // Buy menu
+        if phase == Phase::Buy && !player.killed && is_key_pressed(KeyCode::B) {
+            buying = !buying;
         }
 
-        for (isometry, shape) in compound.shapes() {
-            let pos = isometry.translation.vector;
-            let size = shape.as_cuboid().unwrap().half_extents;
-            draw_cube(
-                DVec3::from_slice(pos.as_slice()).as_vec3(),
-                DVec3::from_slice(size.as_slice()).as_vec3() * 2.0,
-                None,
-                BLACK,
+        let controls = Controls::read();
+        let previous_position = player.position;
+        let moved = !frozen && player.movement(&controls, &map.compound, &map.triggers, &movement);
+        player.update_pose(moved);
+        if player.footstep(previous_position) {
+            sounds.play(
+                Cue::Footstep,
+                if player.walking {
+                    WALKING_FOOTSTEP_VOLUME
+                } else {
+                    FOOTSTEP_VOLUME
+                },
+                0.0,
             );
-            draw_cube_wires(
-                DVec3::from_slice(pos.as_slice()).as_vec3(),
-                DVec3::from_slice(size.as_slice()).as_vec3() * 2.0,
-                WHITE,
+
+            if let Some(packet) = encode(Event::Footstep(Footstep {
+                position: [player.position.x, player.position.y, player.position.z],
+                walking: player.walking,
+            })) {
+                broadcast(&session, &packet);
+            }
+        }
+        if player.hurt > 0.0
+            && let Some(packet) = encode(Event::Hurt(Hurt {
+                damage: mem::take(&mut player.hurt),
+            }))
+        {
+            broadcast(&session, &packet);
+        }
+        if grabbed {
+            player.look(delta);
+        }
+        player.recover(&weapons, delta);
+        player.update_protection();
+
+        if buying {
+            let keys = [
+                KeyCode::Key1,
+                KeyCode::Key2,
+                KeyCode::Key3,
+                KeyCode::Key4,
+                KeyCode::Key5,
+                KeyCode::Key6,
+                KeyCode::Key7,
+                KeyCode::Key8,
+                KeyCode::Key9,
+            ];
+            if let Some(index) = keys.iter().position(|key| is_key_pressed(*key))
+                && index < weapons.len()
+                && player.buy(index, &weapons)
+            {
+                buying = false;
+            }
+        } else if !player.killed {
+            player.switching();
+            player.reloading(&controls, &weapons);
+        }
+
+        if !frozen
+            && let Some(shot) =
+                player.bullets(&controls, &weapons, &map.compound, peers.clone(), moved)
+        {
+            if !shot.hits.is_empty() {
+                sounds.play(Cue::HitMarker, 1.0, 0.0);
+                let peers_read = peers.read().unwrap();
+                indicators.hit(
+                    shot.hits
+                        .iter()
+                        .any(|hit| peers_read.get(&hit.victim).is_some_and(|peer| peer.killed)),
+                );
+            }
+
+            effects.shot(
+                &shot,
+                player.eye()
+                    + player.front * MUZZLE_OFFSET.x
+                    + player.right * MUZZLE_OFFSET.y
+                    + player.up * MUZZLE_OFFSET.z,
             );
+
+            history.shot(local_addr, &shot);
+            if let Some(packet) = encode(Event::Shot(shot)) {
+                broadcast(&session, &packet);
+            }
         }
 
-        draw_cube(
-            vec3(0.0, 0.0, 0.0),
-            vec3((COLUMNS as f32) * SIZE, 0.0, (COLUMNS as f32) * SIZE),
-            None,
-            GRAY,
-        );
+        for cue in player.cues.drain(..) {
+            sounds.play(cue, 1.0, 0.0);
+        }
+        if let Some(name) = player.fired.drain(..).next_back() {
+            notice = Some((name, Instant::now()));
+        }
 
-        set_default_camera();
+        // Bots, moved like the local player and sent out like remote players
+        if let Some(nav) = &nav {
+            let everyone = {
+                let mut everyone = peers.read().unwrap().clone();
+                everyone.insert(local_addr, player.clone());
+                Arc::new(RwLock::new(everyone))
+            };
 
-        draw_line(
-            screen_size.x / 2.0 - CROSSHAIR_LINE_LENGTH,
-            screen_size.y / 2.0,
-            screen_size.x / 2.0 + CROSSHAIR_LINE_LENGTH,
-            screen_size.y / 2.0,
-            CROSSHAIR_THICKNESS,
-            CROSSHAIR_COLOR,
-        );
-        draw_line(
-            screen_size.x / 2.0,
-            screen_size.y / 2.0 - CROSSHAIR_LINE_LENGTH,
-            screen_size.x / 2.0,
-            screen_size.y / 2.0 + CROSSHAIR_LINE_LENGTH,
-            CROSSHAIR_THICKNESS,
-            CROSSHAIR_COLOR,
-        );
+            for bot in &mut bots {
+                let Some(mut body) = peers.read().unwrap().get(&bot.addr).cloned() else {
+                    continue;
+                };
+                let frozen = body.killed || phase == Phase::Buy;
+                let controls = if frozen {
+                    Controls::default()
+                } else {
+                    bot.think(
+                        &mut body,
+                        &everyone.read().unwrap(),
+                        &map.compound,
+                        nav,
+                        delta,
+                    )
+                };
 
-        let bullets_text = format!(
-            "{}/{}",
-            BULLETS_BEFORE_RELOAD - player.bullets_since_last_reload,
-            BULLETS_BEFORE_RELOAD
-        );
-        let bullets_text_measured = measure_text(
-            &bullets_text,
-            None,
-            (BULLETS_FONT_SIZE as f32 * (screen_size.x * screen_size.y)
-                / (DEFAULT_SCREEN_SIZE.x * DEFAULT_SCREEN_SIZE.y)) as u16,
-            1.0,
-        );
-        draw_text(
-            &bullets_text,
-            screen_size.x - bullets_text_measured.width,
-            bullets_text_measured.height,
-            BULLETS_FONT_SIZE as f32,
-            WHITE,
+                let previous_position = body.position;
+                let moved =
+                    !frozen && body.movement(&controls, &map.compound, &map.triggers, &movement);
+                body.update_pose(moved);
+                let footstep = body.footstep(previous_position).then(|| Footstep {
+                    position: body.position.to_array(),
+                    walking: body.walking,
+                });
+                body.recover(&weapons, delta);
+                body.update_protection();
+                if !body.killed {
+                    body.reloading(&controls, &weapons);
+                }
+                let shot = if frozen {
+                    None
+                } else {
+                    body.bullets(&controls, &weapons, &map.compound, everyone.clone(), moved)
+                };
+                body.cues.clear();
+                body.fired.clear();
+                let hurt = mem::take(&mut body.hurt);
+                let tick = body.last_tick_timestamp.elapsed() >= *DURATION_PER_TICK;
+                if tick {
+                    body.last_tick_timestamp = Instant::now();
+                }
+                let query = MoveQuery::of(&body);
+
+                // Hits taken meanwhile stay, what the bot did to itself comes as `Hurt`
+                {
+                    let mut peers_write = peers.write().unwrap();
+                    let Some(peer) = peers_write.get_mut(&bot.addr) else {
+                        continue;
+                    };
+                    body.health = peer.health;
+                    body.killed = peer.killed;
+                    body.pose = peer.pose;
+                    *peer = body;
+                }
+
+                if hurt > 0.0 {
+                    bot_broadcast(
+                        &session,
+                        bot,
+                        &events_sender,
+                        Event::Hurt(Hurt { damage: hurt }),
+                    );
+                }
+                if let Some(footstep) = footstep {
+                    bot_broadcast(&session, bot, &events_sender, Event::Footstep(footstep));
+                }
+                if let Some(shot) = shot {
+                    bot_broadcast(&session, bot, &events_sender, Event::Shot(shot));
+                }
+                if tick {
+                    bot_broadcast(&session, bot, &events_sender, Event::MoveQuery(query));
+                }
+            }
+        }
+
+        clear_background(match (&map.sky, &map.fog) {
+            (Some(sky), _) => sky.horizon,
+            (None, Some(fog)) => fog.color,
+            (None, None) => BLACK,
+        });
+
+        let view = view(
+            &player,
+            killcam.as_ref(),
+            spectator.as_ref(),
+            &peers.read().unwrap(),
+            &map,
         );
+        set_camera(&view.camera());
 
-        let peers_clone = (*peers.read().unwrap()).clone();
+        if let Some(sky) = &map.sky {
+            draw_sky(view.eye.as_vec3(), sky.top, sky.horizon);
+        }
 
-        let config = config::standard();
+        lighting.apply(&map.light, map.fog.as_ref(), view.eye.as_vec3());
+
+        for mesh in &map.meshes {
+            draw_mesh(mesh);
+        }
+
+        let peers_clone = peers.clone();
+
+        if let Some(killcam) = &mut killcam {
+            for player in killcam.players() {
+                style.draw(&player, get_time());
+            }
+            for (shooter, shot) in killcam.shots() {
+                let origin = DVec3::from_array(shot.origin);
+                // Off the camera like the local muzzle when it's the killer firing
+                let muzzle = if shooter == killcam.killer {
+                    view.eye
+                        + view.front * MUZZLE_OFFSET.x
+                        + view.right * MUZZLE_OFFSET.y
+                        + view.up * MUZZLE_OFFSET.z
+                } else {
+                    origin
+                };
+                effects.shot(&shot, muzzle);
+                sounds.play_at(Cue::Gunshot, &view, &map.compound, origin, 1.0);
+            }
+        } else {
+            let peers_read = peers_clone.read().unwrap();
+
+            // Not in the way of the camera looking through their eyes
+            let hidden = spectator
+                .as_ref()
+                .filter(|spectator| spectator.mode == Mode::FirstPerson)
+                .and_then(|spectator| spectator.target);
+            for (peer_host, peer) in peers_read.iter() {
+                if Some(*peer_host) != hidden {
+                    style.draw(peer, get_time());
+                }
+            }
+        }
+
+        gl_use_default_material();
+
+        effects.draw();
+
+        set_default_camera();
+
+        let layout = Layout::new();
+
+        if let Some(killcam) = &killcam {
+            draw_killcam(&layout, killcam.killer, killcam.killer_team());
+        } else {
+            let peers_read = peers.read().unwrap();
+            match &spectator {
+                Some(spectator) => {
+                    let followed = spectator.followed(&peers_read);
+                    // From the followed player, or from the body of a dead one
+                    if let Some(viewer) = followed.or((*role != Role::Spectator).then_some(&player))
+                    {
+                        radar.draw(&layout, viewer, &peers_read, &map.compound);
+                    }
+                    draw_spectating(&layout, spectator, followed);
+                }
+                None => {
+                    crosshair.draw(&layout, player.spread(&weapons[player.weapon], moved));
+                    indicators.draw(&layout, &player);
+                    radar.draw(&layout, &player, &peers_read, &map.compound);
+                    draw_ammo(&layout, &player, &weapons);
+                }
+            }
+        }
+
+        draw_round(
+            &layout,
+            &round.read().unwrap(),
+            (*role != Role::Spectator).then_some(&player),
+        );
+        if buying {
+            draw_buy_menu(&layout, &player, &weapons);
+        }
+        if let Some((name, timestamp)) = &notice
+            && timestamp.elapsed() < NOTICE_DURATION
+        {
+            draw_notice(&layout, name);
+        }
+        if let Some(demo) = &demo {
+            draw_demo(&layout, demo);
+        }
 
-        if player.last_tick_timestamp.elapsed() >= *DURATION_PER_TICK {
+        // Spectators have nothing to tell
+        if *role != Role::Spectator && player.last_tick_timestamp.elapsed() >= *DURATION_PER_TICK {
             player.last_tick_timestamp = Instant::now();
             if player.ticks.len() > TICKS_PER_SECOND {
                 player.ticks.clear()
@@ -308,24 +1075,17 @@ async fn start(
                 player.ticks.push(Some(player.position))
             }
 
-            let mut buf_send = [0; 100];
-            let length = encode_into_slice(
-                Packet {
-                    event: Event::MoveQuery(MoveQuery {
-                        x: player.position.x,
-                        y: player.position.y,
-                        z: player.position.z,
-                    }),
-                },
-                &mut buf_send,
-                config,
-            )
-            .unwrap();
-            let buf_send_filled = &buf_send[..length];
+            if let Some(packet) = encode(Event::MoveQuery(MoveQuery::of(&player))) {
+                broadcast(&session, &packet);
+            }
+            if let Some(recorder) = recorder {
+                recorder.write().unwrap().input(Input::read());
+            }
 
-            let socket_read = socket.read().unwrap();
-            for peer_host in peers_clone.keys() {
-                socket_read.send_to(&buf_send_filled, peer_host).unwrap();
+            if *role == Role::Host
+                && let Some(packet) = encode(Event::RoundState(round.read().unwrap().state()))
+            {
+                broadcast(&session, &packet);
             }
         }
 
@@ -338,57 +1098,300 @@ async fn start(
 async fn main() {
     let mut rng = StdRng::from_os_rng();
 
-    let server = match vars().find(|(key, _)| key == "SERVER") {
-        Some(server) => Some(server.1),
-        None => None,
+    let mut args = args();
+    let demo = args.any(|arg| arg == "--play-demo").then(|| {
+        Demo::load(
+            &args
+                .next()
+                .expect("--play-demo must be followed by a demo."),
+        )
+    });
+
+    let server = vars()
+        .find(|(key, _)| key == "SERVER")
+        .map(|server| server.1);
+    let host = match demo {
+        // Nothing is sent while replaying
+        Some(_) => "127.0.0.1:0".to_string(),
+        None => {
+            vars()
+                .find(|(key, _)| key == "HOST")
+                .expect("HOST must be specified.")
+                .1
+        }
     };
-    let host = vars()
-        .find(|(key, _)| key == "HOST")
-        .expect("HOST must be specified.")
-        .1;
     let socket = Arc::new(RwLock::new(UdpSocket::bind(&host).unwrap()));
 
-    let peers = Arc::new(RwLock::new(HashMap::<SocketAddr, Player>::new()));
+    let peers = Arc::new(RwLock::new(
+        demo.as_ref().map_or_else(HashMap::new, Demo::players),
+    ));
+    let spectators = Arc::new(RwLock::new(HashSet::new()));
+    let round = Arc::new(RwLock::new(Round::new()));
+    let role = if demo.is_some() {
+        Role::Spectator
+    } else if vars().any(|(key, _)| key == "SPECTATE") {
+        assert!(server.is_some(), "SPECTATE needs a SERVER to watch.");
+        Role::Spectator
+    } else if server.is_none() {
+        Role::Host
+    } else {
+        Role::Player
+    };
 
-    let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0));
+    set_pc_assets_folder("assets");
+    let map_path = match &demo {
+        Some(demo) => demo.header.map.clone(),
+        None => vars()
+            .find(|(key, _)| key == "MAP")
+            .map_or("arena.toml".to_string(), |map| map.1),
+    };
+    let map = Map::load(&map_path).await;
+    let weapons = Weapon::load_all("weapons.toml").await;
+    let mut movement = Movement::load(
+        "movement.toml",
+        &vars()
+            .find(|(key, _)| key == "MODE")
+            .map_or(DEFAULT_MODE.to_string(), |mode| mode.1),
+    )
+    .await;
+
+    let mut player = Player::new(
+        map.spawn_position(Team::Red, false, &[], &[], &mut rng),
+        Team::Red,
+    );
+    player.equip_defaults(&weapons);
+    // Never alive, so that only the spectator camera is used
+    player.killed = role == Role::Spectator;
 
     if let Some(server) = server {
         let config = config::standard();
 
-        let mut buf_send = [0; 100];
-        let length = encode_into_slice(
-            Packet {
-                event: Event::RegisterQuery(RegisterQuery {
-                    x: player.position.x,
-                    y: player.position.y,
-                    z: player.position.z,
-                }),
-            },
-            &mut buf_send,
-            config,
-        )
+        let register = encode(Event::RegisterQuery(RegisterQuery {
+            x: player.position.x,
+            y: player.position.y,
+            z: player.position.z,
+            team: None,
+            spectator: role == Role::Spectator,
+        }))
         .unwrap();
-        let buf_send_filled = &buf_send[..length];
         let socket_read = socket.read().unwrap();
-        socket_read.send_to(&buf_send_filled, server).unwrap();
+        socket_read.send_to(&register, &server).unwrap();
+        let mut register_timestamp = Instant::now();
 
-        let mut buf = [0; 100];
-        let (amt, src) = socket_read.recv_from(&mut buf).unwrap();
+        // In as many parts as it takes, in any order and among whatever the host already sends,
+        // asking again until all of them made it
+        socket_read.set_read_timeout(Some(JOIN_RETRY)).unwrap();
+        let mut buf = [0; PACKET_SIZE];
+        let mut parts: Vec<Option<Peers>> = Vec::new();
+        let src = loop {
+            if register_timestamp.elapsed() >= JOIN_RETRY {
+                eprintln!("Warning: no full answer from {server} yet, asking again");
+                socket_read.send_to(&register, &server).unwrap();
+                register_timestamp = Instant::now();
+            }
 
-        let (packet, _): (Packet, _) = decode_from_slice(&buf[..amt], config).unwrap();
-        if let Event::Peers(query) = packet.event {
-            let mut peers_write = peers.write().unwrap();
+            let Ok((amt, src)) = socket_read.recv_from(&mut buf) else {
+                continue;
+            };
+            let Ok((packet, _)) = decode_from_slice::<Packet, _>(&buf[..amt], config) else {
+                continue;
+            };
+            let Event::Peers(part) = packet.event else {
+                continue;
+            };
+            if parts.len() != part.parts as usize {
+                parts = (0..part.parts).map(|_| None).collect();
+            }
+            if let Some(slot) = parts.get_mut(part.part as usize) {
+                *slot = Some(part);
+            }
+            if !parts.is_empty() && parts.iter().all(Option::is_some) {
+                break src;
+            }
+        };
+        socket_read.set_read_timeout(None).unwrap();
 
-            for (peer_host, pos) in query.peers {
-                if peer_host != src {
-                    socket_read.send_to(&buf_send_filled, &peer_host).unwrap();
-                }
-                peers_write.insert(peer_host, Player::new(DVec3::from_slice(&pos)));
+        let mut parts = parts.into_iter().flatten();
+        let mut query = parts.next().unwrap();
+        for part in parts {
+            query.peers.extend(part.peers);
+            query.spectators.extend(part.spectators);
+        }
+
+        player.team = query.team;
+        movement = query.movement;
+        let (mut teammates, mut enemies) = (Vec::new(), Vec::new());
+        for (_, position, team) in &query.peers {
+            if *team == player.team {
+                teammates.push(DVec3::from_array(*position));
+            } else {
+                enemies.push(DVec3::from_array(*position));
             }
-        } else {
-            panic!()
+        }
+        if role != Role::Spectator {
+            player.position =
+                map.spawn_position(player.team, false, &teammates, &enemies, &mut rng);
+            player.protect();
+        }
+
+        let register = encode(Event::RegisterQuery(RegisterQuery {
+            x: player.position.x,
+            y: player.position.y,
+            z: player.position.z,
+            team: Some(player.team),
+            spectator: role == Role::Spectator,
+        }))
+        .unwrap();
+
+        let mut peers_write = peers.write().unwrap();
+
+        for (peer_host, pos, team) in query.peers {
+            if peer_host != src {
+                socket_read.send_to(&register, peer_host).unwrap();
+            }
+            peers_write.insert(peer_host, Player::new(DVec3::from_slice(&pos), team));
+        }
+        // Told about the newcomer, so that they can be followed
+        if role != Role::Spectator {
+            for spectator in &query.spectators {
+                socket_read.send_to(&register, spectator).unwrap();
+            }
+            spectators.write().unwrap().extend(query.spectators);
+        }
+    }
+
+    // Simulated by the host, everyone else gets them as peers
+    let bot_count = vars()
+        .find(|(key, _)| key == "BOTS")
+        .map_or(0, |bots| bots.1.parse().expect("BOTS must be a number."));
+    let mut bots = Vec::new();
+    if bot_count > 0 && role != Role::Host {
+        eprintln!("Warning: only the host can add bots, ignoring BOTS");
+    } else if bot_count > 0 {
+        let skill = Skill::load(
+            "bots.toml",
+            &vars()
+                .find(|(key, _)| key == "BOT_SKILL")
+                .map_or(DEFAULT_BOT_SKILL.to_string(), |skill| skill.1),
+        )
+        .await;
+        let ip = socket.read().unwrap().local_addr().unwrap().ip();
+        let mut peers_write = peers.write().unwrap();
+        for _ in 0..bot_count {
+            let bot = Bot::new(UdpSocket::bind((ip, 0)).unwrap(), skill.clone(), &mut rng);
+
+            // Into the smaller team, counting the host
+            let reds = peers_write
+                .values()
+                .map(|peer| peer.team)
+                .chain([player.team])
+                .filter(|team| *team == Team::Red)
+                .count();
+            let team = if reds * 2 <= peers_write.len() + 1 {
+                Team::Red
+            } else {
+                Team::Blue
+            };
+
+            let mut body = Player::new(
+                spawn_position(&map, team, false, &peers_write, &mut rng),
+                team,
+            );
+            body.equip_defaults(&weapons);
+            peers_write.insert(bot.addr, body);
+            bots.push(bot);
         }
     }
 
-    start(player, peers, socket, &mut rng).await;
+    let recorder = vars().find(|(key, _)| key == "RECORD").map(|path| {
+        let local = socket.read().unwrap().local_addr().unwrap();
+        let mut players = peers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(peer_host, peer)| (*peer_host, peer.position.to_array(), peer.team))
+            .collect::<Vec<_>>();
+        if role != Role::Spectator {
+            players.push((local, player.position.to_array(), player.team));
+        }
+        Arc::new(RwLock::new(Recorder::create(
+            &path.1,
+            &Header {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                map: map_path,
+                local,
+                players,
+            },
+        )))
+    });
+
+    start(
+        player,
+        Session {
+            peers,
+            spectators,
+            round,
+            socket,
+            role,
+            recorder,
+            bots: bots.iter().map(|bot| bot.addr).collect(),
+        },
+        Assets {
+            map,
+            weapons,
+            movement,
+        },
+        demo,
+        bots,
+        &mut rng,
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peers_killed_last_round_survive_a_hit() {
+        let peer_host = "127.0.0.1:5001".parse().unwrap();
+        let mut peers = HashMap::from([(peer_host, Player::new(DVec3::ZERO, Team::Blue))]);
+        assert!(peers.get_mut(&peer_host).unwrap().damage(MAX_HEALTH));
+
+        new_round(&mut peers);
+        let peer = peers.get_mut(&peer_host).unwrap();
+        assert!(!peer.damage(MAX_HEALTH / 2.0));
+        assert_eq!(peer.health, MAX_HEALTH / 2.0);
+    }
+
+    #[test]
+    fn peers_fit_in_packets_however_many_there_are() {
+        let address = "[ffff::ffff]:65535".parse().unwrap();
+        let packets = split_peers(Peers {
+            peers: vec![(address, [1e9; 3], Team::Red); 40],
+            spectators: vec![address; 300],
+            team: Team::Blue,
+            movement: Movement::default(),
+            part: 0,
+            parts: 0,
+        });
+        assert!(packets.len() > 1);
+
+        let (mut peers, mut spectators) = (0, 0);
+        for (index, packet) in packets.iter().enumerate() {
+            assert!(packet.len() <= PACKET_SIZE);
+            let (packet, _): (Packet, _) = decode_from_slice(packet, config::standard()).unwrap();
+            let Event::Peers(part) = packet.event else {
+                panic!("Not an answer to a registration.");
+            };
+            assert_eq!(
+                (part.part as usize, part.parts as usize),
+                (index, packets.len())
+            );
+            assert_eq!(part.team, Team::Blue);
+            peers += part.peers.len();
+            spectators += part.spectators.len();
+        }
+        assert_eq!((peers, spectators), (40, 300));
+    }
 }
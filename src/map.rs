@@ -0,0 +1,513 @@
+use crate::{
+    consts::*,
+    render::{Fog, Light, box_mesh},
+    round::Team,
+};
+use ::rand::{Rng, rngs::StdRng, seq::SliceRandom};
+use macroquad::prelude::*;
+use parry3d_f64::{
+    bounding_volume::Aabb,
+    math::{Isometry, Point, Vector},
+    na::{Quaternion, Translation3, UnitQuaternion},
+    query::{Ray, RayCast, intersection_test},
+    shape::{Compound, Cuboid, SharedShape},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Where players of one side spawn: the points if there are any, otherwise anywhere in a
+/// rectangle on the floor.
+#[derive(Clone)]
+pub struct SpawnZone {
+    pub min: DVec2,
+    pub max: DVec2,
+    /// Player positions, which stand half a player above the points in the file.
+    pub points: Vec<DVec3>,
+}
+
+impl SpawnZone {
+    pub fn sample(&self, rng: &mut StdRng) -> DVec3 {
+        dvec3(
+            rng.random_range(self.min.x..self.max.x),
+            PLAYER_SIZE.y,
+            rng.random_range(self.min.y..self.max.y),
+        )
+    }
+
+    /// Shuffled, so that equally good spawns are picked at random.
+    fn candidates(&self, rng: &mut StdRng) -> Vec<DVec3> {
+        let mut candidates = if self.points.is_empty() {
+            (0..SPAWN_CANDIDATES).map(|_| self.sample(rng)).collect()
+        } else {
+            self.points.clone()
+        };
+        candidates.shuffle(rng);
+        candidates
+    }
+}
+
+pub fn isometry(position: DVec3, rotation: DQuat) -> Isometry<f64> {
+    Isometry::from_parts(
+        Translation3::new(position.x, position.y, position.z),
+        UnitQuaternion::new_unchecked(Quaternion::new(
+            rotation.w, rotation.x, rotation.y, rotation.z,
+        )),
+    )
+}
+
+/// Turns degrees around y, then x, then z into a rotation.
+fn rotation(degrees: [f64; 3]) -> DQuat {
+    let [x, y, z] = degrees.map(f64::to_radians);
+    DQuat::from_euler(EulerRot::YXZ, y, x, z)
+}
+
+fn rgb(color: [u8; 3]) -> Color {
+    Color::from_rgba(color[0], color[1], color[2], 255)
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn white() -> [u8; 3] {
+    [255; 3]
+}
+
+#[derive(Deserialize)]
+struct BoxFile {
+    center: [f64; 3],
+    half_extents: [f64; 3],
+    /// Degrees around y, then x, then z, which tilts boxes into ramps.
+    #[serde(default)]
+    rotation: [f64; 3],
+    texture: Option<String>,
+    #[serde(default = "white")]
+    color: [u8; 3],
+    /// Texture repeats per meter.
+    #[serde(default = "one")]
+    texture_scale: f32,
+}
+
+/// What a volume the player can walk into does.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Trigger {
+    /// Climbed with W and S while inside.
+    Ladder,
+    /// Launches the player with `velocity`, in meters per frame.
+    JumpPad {
+        velocity: [f64; 3],
+    },
+    /// Moves the player so that it stands at `destination`, facing `yaw` degrees if given.
+    Teleporter {
+        destination: [f64; 3],
+        yaw: Option<f64>,
+    },
+    Kill,
+    /// Fires the named event each time a player walks in.
+    Event {
+        name: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct TriggerFile {
+    center: [f64; 3],
+    half_extents: [f64; 3],
+    #[serde(default)]
+    rotation: [f64; 3],
+    #[serde(flatten)]
+    trigger: Trigger,
+}
+
+/// Volumes of the map that can be walked into, kept apart from the solid boxes.
+pub struct Triggers {
+    /// `None` when the map has no triggers, since a compound can't be empty.
+    compound: Option<Compound>,
+    /// Indexed like the shapes of `compound`.
+    pub triggers: Vec<Trigger>,
+}
+
+impl Triggers {
+    /// `shapes` and `triggers` go together by index.
+    pub fn new(shapes: Vec<(Isometry<f64>, SharedShape)>, triggers: Vec<Trigger>) -> Self {
+        Self {
+            compound: (!shapes.is_empty()).then(|| Compound::new(shapes)),
+            triggers,
+        }
+    }
+
+    /// Indices of the triggers a player at `position` is inside of.
+    pub fn touching(&self, position: DVec3) -> Vec<usize> {
+        let Some(compound) = &self.compound else {
+            return Vec::new();
+        };
+
+        let player = isometry(position, DQuat::IDENTITY);
+        let cuboid = Cuboid::new(PLAYER_SIZE);
+        let center = Point::new(position.x, position.y, position.z);
+        let mut candidates = Vec::new();
+        compound.qbvh().intersect_aabb(
+            &Aabb::from_half_extents(center, PLAYER_SIZE),
+            &mut candidates,
+        );
+
+        let shapes = compound.shapes();
+        let mut touching = candidates
+            .into_iter()
+            .map(|index| index as usize)
+            .filter(|&index| {
+                let (isometry, shape) = &shapes[index];
+                intersection_test(isometry, shape.as_ref(), &player, &cuboid).unwrap()
+            })
+            .collect::<Vec<_>>();
+        touching.sort_unstable();
+        touching
+    }
+}
+
+#[derive(Deserialize)]
+struct SpawnFile {
+    /// `(x, z)` corners.
+    min: [f64; 2],
+    max: [f64; 2],
+    /// Where the feet go, used instead of the rectangle when given.
+    #[serde(default)]
+    points: Vec<[f64; 3]>,
+}
+
+#[derive(Deserialize)]
+struct FloorFile {
+    /// Half of the side of the square floor around the origin.
+    half_size: f64,
+    texture: Option<String>,
+    #[serde(default = "white")]
+    color: [u8; 3],
+    #[serde(default = "one")]
+    texture_scale: f32,
+}
+
+#[derive(Deserialize)]
+struct LightFile {
+    direction: [f32; 3],
+    #[serde(default = "white")]
+    color: [u8; 3],
+    ambient: f32,
+}
+
+#[derive(Deserialize)]
+struct FogFile {
+    color: [u8; 3],
+    start: f32,
+    end: f32,
+}
+
+#[derive(Deserialize)]
+struct SkyFile {
+    top: [u8; 3],
+    horizon: [u8; 3],
+}
+
+#[derive(Deserialize)]
+struct MapFile {
+    /// Name to file, relative to `assets`.
+    #[serde(default)]
+    textures: HashMap<String, String>,
+    floor: FloorFile,
+    light: LightFile,
+    fog: Option<FogFile>,
+    sky: Option<SkyFile>,
+    /// One per side.
+    spawn: [SpawnFile; 2],
+    #[serde(rename = "box")]
+    boxes: Vec<BoxFile>,
+    #[serde(default, rename = "trigger")]
+    triggers: Vec<TriggerFile>,
+}
+
+impl MapFile {
+    /// Panics on what the game can't work with, naming `path`.
+    fn validate(&self, path: &str) {
+        assert!(!self.boxes.is_empty(), "Map {path} has no boxes.");
+        for (side, spawn) in self.spawn.iter().enumerate() {
+            assert!(
+                !spawn.points.is_empty()
+                    || (spawn.min[0] < spawn.max[0] && spawn.min[1] < spawn.max[1]),
+                "Spawn {side} of map {path} has neither points nor an area to pick from."
+            );
+        }
+    }
+}
+
+pub struct Sky {
+    pub top: Color,
+    pub horizon: Color,
+}
+
+pub struct Map {
+    pub compound: Compound,
+    /// Half of the side of the square floor around the origin.
+    pub floor_size: f64,
+    pub triggers: Triggers,
+    /// Indexed by side, see `Team::side`.
+    pub spawn_zones: [SpawnZone; 2],
+    pub light: Light,
+    pub fog: Option<Fog>,
+    pub sky: Option<Sky>,
+    /// The floor and every box, drawn with `Lighting`.
+    pub meshes: Vec<Mesh>,
+}
+
+impl Map {
+    /// Loads a map described by a file in `assets`. Textures that fail to load are reported and
+    /// drawn plain.
+    pub async fn load(path: &str) -> Self {
+        let file: MapFile =
+            toml::from_str(&load_string(path).await.unwrap()).expect("Invalid map file.");
+        file.validate(path);
+
+        let mut textures = HashMap::new();
+        for (name, texture_path) in &file.textures {
+            match load_texture(texture_path).await {
+                Ok(texture) => {
+                    textures.insert(name.clone(), texture);
+                }
+                Err(e) => eprintln!("Warning: couldn't load texture {texture_path}: {e}"),
+            }
+        }
+        let texture = |name: &Option<String>| {
+            let name = name.as_ref()?;
+            let texture = textures.get(name).cloned();
+            if texture.is_none() && !file.textures.contains_key(name) {
+                eprintln!("Warning: no texture named {name} in {path}");
+            }
+            texture
+        };
+
+        let floor = &file.floor;
+        // A thin slab with its top at 0 so the underside doesn't fight with it
+        let mut meshes = vec![box_mesh(
+            vec3(0.0, -0.01, 0.0),
+            Quat::IDENTITY,
+            vec3(floor.half_size as f32, 0.01, floor.half_size as f32),
+            rgb(floor.color),
+            texture(&floor.texture),
+            floor.texture_scale,
+        )];
+
+        let mut shapes = Vec::with_capacity(file.boxes.len());
+        for bx in &file.boxes {
+            let center = DVec3::from_array(bx.center);
+            let rotation = rotation(bx.rotation);
+            let [hx, hy, hz] = bx.half_extents;
+            shapes.push((
+                isometry(center, rotation),
+                SharedShape::new(Cuboid::new(Vector::new(hx, hy, hz))),
+            ));
+            meshes.push(box_mesh(
+                center.as_vec3(),
+                rotation.as_quat(),
+                DVec3::from_array(bx.half_extents).as_vec3(),
+                rgb(bx.color),
+                texture(&bx.texture),
+                bx.texture_scale,
+            ));
+        }
+
+        let trigger_shapes = file
+            .triggers
+            .iter()
+            .map(|trigger| {
+                let [hx, hy, hz] = trigger.half_extents;
+                (
+                    isometry(
+                        DVec3::from_array(trigger.center),
+                        rotation(trigger.rotation),
+                    ),
+                    SharedShape::new(Cuboid::new(Vector::new(hx, hy, hz))),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            compound: Compound::new(shapes),
+            floor_size: floor.half_size,
+            triggers: Triggers::new(
+                trigger_shapes,
+                file.triggers
+                    .into_iter()
+                    .map(|trigger| trigger.trigger)
+                    .collect(),
+            ),
+            spawn_zones: file.spawn.map(|spawn| SpawnZone {
+                min: DVec2::from_array(spawn.min),
+                max: DVec2::from_array(spawn.max),
+                points: spawn
+                    .points
+                    .iter()
+                    .map(|point| DVec3::from_array(*point) + DVec3::Y * PLAYER_SIZE.y)
+                    .collect(),
+            }),
+            light: Light {
+                direction: Vec3::from_array(file.light.direction),
+                color: rgb(file.light.color),
+                ambient: file.light.ambient,
+            },
+            fog: file.fog.map(|fog| Fog {
+                color: rgb(fog.color),
+                start: fog.start,
+                end: fog.end,
+            }),
+            sky: file.sky.map(|sky| Sky {
+                top: rgb(sky.top),
+                horizon: rgb(sky.horizon),
+            }),
+            meshes,
+        }
+    }
+
+    /// Picks a spawn of the side of `team` away from teammates, then out of sight of `enemies`,
+    /// then as far from them as possible.
+    pub fn spawn_position(
+        &self,
+        team: Team,
+        swapped: bool,
+        teammates: &[DVec3],
+        enemies: &[DVec3],
+        rng: &mut StdRng,
+    ) -> DVec3 {
+        let candidates = self.spawn_zones[team.side(swapped)].candidates(rng);
+        let eye = |position: DVec3| position + DVec3::Y * EYE_OFFSET;
+
+        let score = |candidate: DVec3| {
+            let free = teammates
+                .iter()
+                .all(|teammate| teammate.distance(candidate) >= SPAWN_CLEARANCE);
+            let hidden = enemies.iter().all(|&enemy| {
+                let (from, to) = (eye(enemy), eye(candidate));
+                let distance = from.distance(to);
+                let direction = (to - from) / distance;
+                let ray = Ray::new(
+                    Point::new(from.x, from.y, from.z),
+                    Vector::new(direction.x, direction.y, direction.z),
+                );
+                self.compound
+                    .cast_ray(&Isometry::identity(), &ray, distance, true)
+                    .is_some()
+            });
+            let distance = enemies
+                .iter()
+                .map(|enemy| enemy.distance(candidate))
+                .fold(f64::INFINITY, f64::min);
+            (free, hidden, distance)
+        };
+
+        candidates
+            .into_iter()
+            .map(|candidate| (score(candidate), candidate))
+            .max_by(|(a, _), (b, _)| (a.0, a.1).cmp(&(b.0, b.1)).then(a.2.total_cmp(&b.2)))
+            .unwrap()
+            .1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::rand::SeedableRng;
+
+    /// A wall at x = 0 covering z < 0, with spawn points on both sides of where it ends.
+    fn map() -> Map {
+        let zone = SpawnZone {
+            min: DVec2::ZERO,
+            max: DVec2::ZERO,
+            points: [
+                dvec3(-2.0, 0.0, -5.0),
+                dvec3(-2.0, 0.0, 5.0),
+                dvec3(-10.0, 0.0, 5.0),
+            ]
+            .map(|point| point + DVec3::Y * PLAYER_SIZE.y)
+            .to_vec(),
+        };
+        Map {
+            compound: Compound::new(vec![(
+                isometry(dvec3(0.0, 0.0, -10.0), DQuat::IDENTITY),
+                SharedShape::cuboid(0.1, 10.0, 10.0),
+            )]),
+            floor_size: 25.0,
+            triggers: Triggers::new(Vec::new(), Vec::new()),
+            spawn_zones: [zone.clone(), zone],
+            light: Light {
+                direction: Vec3::Y,
+                color: WHITE,
+                ambient: 1.0,
+            },
+            fog: None,
+            sky: None,
+            meshes: Vec::new(),
+        }
+    }
+
+    const MAP_FILE: &str = r#"
+        floor = { half_size = 10.0 }
+        light = { direction = [0.0, 1.0, 0.0], ambient = 1.0 }
+        spawn = [{ min = [-5.0, -5.0], max = [-4.0, 5.0] }, { min = [4.0, -5.0], max = [5.0, 5.0] }]
+        box = [{ center = [0.0, 1.0, 0.0], half_extents = [1.0, 1.0, 1.0] }]
+    "#;
+
+    #[test]
+    fn validates_map_files() {
+        toml::from_str::<MapFile>(include_str!("../assets/arena.toml"))
+            .unwrap()
+            .validate("arena.toml");
+        toml::from_str::<MapFile>(MAP_FILE)
+            .unwrap()
+            .validate("test.toml");
+    }
+
+    #[test]
+    #[should_panic(expected = "Spawn 1 of map test.toml")]
+    fn rejects_degenerate_spawn_zones() {
+        toml::from_str::<MapFile>(&MAP_FILE.replace("max = [5.0, 5.0]", "max = [5.0, -5.0]"))
+            .unwrap()
+            .validate("test.toml");
+    }
+
+    #[test]
+    #[should_panic(expected = "Map test.toml has no boxes")]
+    fn rejects_maps_without_boxes() {
+        toml::from_str::<MapFile>(&MAP_FILE.replace(
+            "box = [{ center = [0.0, 1.0, 0.0], half_extents = [1.0, 1.0, 1.0] }]",
+            "box = []",
+        ))
+        .unwrap()
+        .validate("test.toml");
+    }
+
+    fn spawn(map: &Map, teammates: &[DVec3], enemies: &[DVec3]) -> DVec3 {
+        let mut rng = StdRng::seed_from_u64(0);
+        map.spawn_position(Team::Red, false, teammates, enemies, &mut rng)
+    }
+
+    #[test]
+    fn spawns_out_of_sight_before_far_away() {
+        let map = map();
+        let enemy = dvec3(2.0, PLAYER_SIZE.y, 2.0);
+        assert_eq!(spawn(&map, &[], &[enemy]).z, -5.0);
+    }
+
+    #[test]
+    fn spawns_far_from_enemies_that_see_every_spawn() {
+        let map = map();
+        let enemy = dvec3(-6.0, PLAYER_SIZE.y, 10.0);
+        assert_eq!(spawn(&map, &[], &[enemy]).z, -5.0);
+    }
+
+    #[test]
+    fn spawns_away_from_teammates() {
+        let map = map();
+        let enemy = dvec3(2.0, PLAYER_SIZE.y, 2.0);
+        let teammate = dvec3(-2.0, PLAYER_SIZE.y, -5.0);
+        assert_ne!(spawn(&map, &[teammate], &[enemy]).z, -5.0);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{consts::*, map::isometry, player::Player, render::box_mesh, round::Team};
+use bincode::{Decode, Encode};
+use macroquad::prelude::*;
+use parry3d_f64::math::Isometry;
+use serde::Deserialize;
+use std::f64::consts::FRAC_PI_2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Encode, Decode)]
+pub enum Pose {
+    Idle,
+    Run,
+    Crouch,
+    Jump,
+    Death,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Part {
+    Head,
+    Torso,
+    LeftArm,
+    RightArm,
+    LeftLeg,
+    RightLeg,
+    /// Drawn but can't be shot.
+    Gun,
+}
+
+/// An oriented box of the model, in world space.
+pub struct Bone {
+    pub part: Part,
+    pub center: DVec3,
+    pub rotation: DQuat,
+    pub half_extents: DVec3,
+}
+
+impl Bone {
+    pub fn isometry(&self) -> Isometry<f64> {
+        isometry(self.center, self.rotation)
+    }
+}
+
+/// Lays out the model of a player standing at `position` in model space, where x is forward,
+/// y is up and z is to the right, and then turns it by `yaw`.
+///
+/// `time` drives the running animation.
+pub fn bones(position: DVec3, yaw: f64, pitch: f64, pose: Pose, time: f64) -> Vec<Bone> {
+    let feet = position - DVec3::Y * PLAYER_SIZE.y;
+
+    // Leg angles from the vertical, positive is forward
+    let (left_leg, right_leg) = match pose {
+        Pose::Idle | Pose::Death => (0.0, 0.0),
+        Pose::Run => {
+            let swing = (time * RUN_CYCLE_SPEED).sin() * RUN_LEG_SWING;
+            (swing, -swing)
+        }
+        Pose::Crouch => {
+            // Bent so far that the hips drop by the crouch level
+            let angle = (1.0 - CROUCH_LEVEL_CONST / LEG_LENGTH).acos();
+            (angle, -angle)
+        }
+        Pose::Jump => (JUMP_LEG_TUCK, -JUMP_LEG_TUCK),
+    };
+    let hip = LEG_LENGTH * left_leg.cos().max(right_leg.cos());
+    let neck = hip + TORSO_LENGTH;
+    let shoulder = neck - ARM_WIDTH;
+
+    // Arms hold the gun along the aim
+    let aim = DQuat::from_rotation_z(FRAC_PI_2 + pitch);
+
+    let local = [
+        (
+            Part::LeftLeg,
+            dvec3(0.0, hip, -LIMB_SPACING),
+            DQuat::from_rotation_z(left_leg),
+            dvec3(0.0, -LEG_LENGTH / 2.0, 0.0),
+            dvec3(LIMB_WIDTH, LEG_LENGTH / 2.0, LIMB_WIDTH),
+        ),
+        (
+            Part::RightLeg,
+            dvec3(0.0, hip, LIMB_SPACING),
+            DQuat::from_rotation_z(right_leg),
+            dvec3(0.0, -LEG_LENGTH / 2.0, 0.0),
+            dvec3(LIMB_WIDTH, LEG_LENGTH / 2.0, LIMB_WIDTH),
+        ),
+        (
+            Part::Torso,
+            dvec3(0.0, hip, 0.0),
+            DQuat::IDENTITY,
+            dvec3(0.0, TORSO_LENGTH / 2.0, 0.0),
+            dvec3(TORSO_DEPTH, TORSO_LENGTH / 2.0, TORSO_WIDTH),
+        ),
+        (
+            Part::Head,
+            dvec3(0.0, neck, 0.0),
+            DQuat::from_rotation_z(pitch),
+            dvec3(0.0, HEAD_SIZE, 0.0),
+            DVec3::splat(HEAD_SIZE),
+        ),
+        (
+            Part::LeftArm,
+            dvec3(0.0, shoulder, -(TORSO_WIDTH + ARM_WIDTH)),
+            aim,
+            dvec3(0.0, -ARM_LENGTH / 2.0, 0.0),
+            dvec3(ARM_WIDTH, ARM_LENGTH / 2.0, ARM_WIDTH),
+        ),
+        (
+            Part::RightArm,
+            dvec3(0.0, shoulder, TORSO_WIDTH + ARM_WIDTH),
+            aim,
+            dvec3(0.0, -ARM_LENGTH / 2.0, 0.0),
+            dvec3(ARM_WIDTH, ARM_LENGTH / 2.0, ARM_WIDTH),
+        ),
+        (
+            Part::Gun,
+            dvec3(0.0, shoulder, 0.0),
+            aim,
+            dvec3(0.0, -ARM_LENGTH - GUN_LENGTH / 2.0, 0.0),
+            dvec3(GUN_WIDTH, GUN_LENGTH / 2.0, GUN_WIDTH),
+        ),
+    ];
+
+    // The dead fall on their back around the feet
+    let root = DQuat::from_rotation_y(-yaw)
+        * if pose == Pose::Death {
+            DQuat::from_rotation_z(FRAC_PI_2)
+        } else {
+            DQuat::IDENTITY
+        };
+
+    local
+        .into_iter()
+        .map(|(part, pivot, rotation, offset, half_extents)| {
+            let rotation = root * rotation;
+            Bone {
+                part,
+                center: feet + root * pivot + rotation * offset,
+                rotation,
+                half_extents,
+            }
+        })
+        .collect()
+}
+
+fn rgb(color: [u8; 3]) -> Color {
+    Color::from_rgba(color[0], color[1], color[2], 255)
+}
+
+#[derive(Deserialize, Clone, Copy)]
+struct TeamColors {
+    shirt: [u8; 3],
+    pants: [u8; 3],
+}
+
+#[derive(Deserialize)]
+struct ModelColors {
+    skin: [u8; 3],
+    gun: [u8; 3],
+    dead: [u8; 3],
+    red: TeamColors,
+    blue: TeamColors,
+}
+
+/// Colors of the player model, from `assets/model.toml`.
+pub struct Style {
+    colors: ModelColors,
+}
+
+impl Style {
+    pub async fn load(path: &str) -> Self {
+        Self {
+            colors: toml::from_str(&load_string(path).await.unwrap()).expect("Invalid model file."),
+        }
+    }
+
+    fn color(&self, part: Part, team: Team, dead: bool) -> Color {
+        if dead {
+            return rgb(self.colors.dead);
+        }
+
+        let team = match team {
+            Team::Red => self.colors.red,
+            Team::Blue => self.colors.blue,
+        };
+        rgb(match part {
+            Part::Head => self.colors.skin,
+            Part::Torso | Part::LeftArm | Part::RightArm => team.shirt,
+            Part::LeftLeg | Part::RightLeg => team.pants,
+            Part::Gun => self.colors.gun,
+        })
+    }
+
+    /// Must be called within the 3D pass, with `Lighting` applied.
+    pub fn draw(&self, player: &Player, time: f64) {
+        for bone in bones(player.position, player.yaw, player.pitch, player.pose, time) {
+            draw_mesh(&box_mesh(
+                bone.center.as_vec3(),
+                bone.rotation.as_quat(),
+                bone.half_extents.as_vec3(),
+                self.color(bone.part, player.team, player.pose == Pose::Death),
+                None,
+                1.0,
+            ));
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{consts::*, weapon::seconds};
+use bincode::{Decode, Encode};
+use macroquad::prelude::*;
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
+
+/// How players accelerate, slow down and jump, per game mode from `assets/movement.toml`. Chosen
+/// by the host and sent to everyone who joins.
+#[derive(Clone, PartialEq, Debug, Deserialize, Encode, Decode)]
+#[serde(default)]
+pub struct Movement {
+    /// Top running speed, walking and crouching are shares of it.
+    pub max_speed: f64,
+    pub acceleration: f64,
+    pub friction: f64,
+    pub stop_speed: f64,
+    pub air_acceleration: f64,
+    pub air_speed: f64,
+    pub jump_velocity: f64,
+    pub gravity: f64,
+    /// Whether turning in the air can build up speed beyond `max_speed`.
+    pub strafe_jumping: bool,
+    /// Whether jumping keeps the speed from before, rather than being capped at `max_speed`.
+    pub bunny_hopping: bool,
+    pub accurate_speed: f64,
+    pub fall_damage_speed: f64,
+    pub fall_damage: f64,
+    pub hard_landing_speed: f64,
+    pub landing_slowdown: f64,
+    #[serde(deserialize_with = "seconds")]
+    pub landing_slowdown_duration: Duration,
+    /// Up and down, on ladders.
+    pub ladder_speed: f64,
+}
+
+impl Default for Movement {
+    fn default() -> Self {
+        Self {
+            max_speed: MOVE_SPEED,
+            acceleration: GROUND_ACCELERATION,
+            friction: FRICTION,
+            stop_speed: STOP_SPEED,
+            air_acceleration: AIR_ACCELERATION,
+            air_speed: AIR_SPEED,
+            jump_velocity: JUMP_VELOCITY,
+            gravity: GRAVITY,
+            strafe_jumping: true,
+            bunny_hopping: false,
+            accurate_speed: ACCURATE_SPEED,
+            fall_damage_speed: FALL_DAMAGE_SPEED,
+            fall_damage: FALL_DAMAGE,
+            hard_landing_speed: HARD_LANDING_SPEED,
+            landing_slowdown: LANDING_SLOWDOWN,
+            landing_slowdown_duration: LANDING_SLOWDOWN_DURATION,
+            ladder_speed: LADDER_SPEED,
+        }
+    }
+}
+
+impl Movement {
+    pub async fn load(path: &str, mode: &str) -> Self {
+        let mut modes: HashMap<String, Self> =
+            toml::from_str(&load_string(path).await.unwrap()).expect("Invalid movement file.");
+        modes
+            .remove(mode)
+            .unwrap_or_else(|| panic!("Unknown mode {mode}."))
+    }
+
+    /// Slows `velocity` down along the floor while standing.
+    pub fn friction(&self, velocity: &mut DVec3) {
+        let speed = velocity.with_y(0.0).length();
+        if speed == 0.0 {
+            return;
+        }
+
+        let drop = speed.max(self.stop_speed) * self.friction;
+        let scale = (speed - drop).max(0.0) / speed;
+        velocity.x *= scale;
+        velocity.z *= scale;
+    }
+
+    /// Speeds `velocity` up towards `speed` along `direction`. Only what's along `direction`
+    /// counts, so pressing against the movement stops it quickly and turning in the air gains
+    /// speed.
+    pub fn accelerate(&self, velocity: &mut DVec3, direction: DVec3, speed: f64, airborne: bool) {
+        let (acceleration, wanted) = if airborne {
+            (self.air_acceleration, speed.min(self.air_speed))
+        } else {
+            (self.acceleration, speed)
+        };
+
+        let missing = wanted - velocity.dot(direction);
+        if missing <= 0.0 {
+            return;
+        }
+
+        let before = velocity.with_y(0.0).length();
+        *velocity += direction * (acceleration * speed).min(missing);
+
+        // Still steered, just no faster
+        let after = velocity.with_y(0.0).length();
+        let limit = before.max(speed);
+        if airborne && !self.strafe_jumping && after > limit {
+            velocity.x *= limit / after;
+            velocity.z *= limit / after;
+        }
+    }
+
+    /// Health lost when landing at `speed`.
+    pub fn fall_damage(&self, speed: f64) -> f64 {
+        (speed - self.fall_damage_speed).max(0.0) * self.fall_damage
+    }
+
+    /// Caps the speed along the floor when jumping without bunny hopping.
+    pub fn jump(&self, velocity: &mut DVec3) {
+        let speed = velocity.with_y(0.0).length();
+        if !self.bunny_hopping && speed > self.max_speed {
+            velocity.x *= self.max_speed / speed;
+            velocity.z *= self.max_speed / speed;
+        }
+        velocity.y = self.jump_velocity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames until the player running along x is slow enough to be accurate.
+    fn frames_to_stop(movement: &Movement, direction: DVec3) -> usize {
+        let mut velocity = DVec3::X * movement.max_speed;
+        (1..100)
+            .find(|_| {
+                movement.friction(&mut velocity);
+                movement.accelerate(&mut velocity, direction, movement.max_speed, false);
+                velocity.length() < movement.max_speed * movement.accurate_speed
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn counter_strafing_stops_sooner() {
+        let movement = Movement::default();
+        assert!(frames_to_stop(&movement, DVec3::NEG_X) < frames_to_stop(&movement, DVec3::ZERO));
+    }
+
+    /// Speed after turning in the air while strafing towards the side of the turn.
+    fn strafe(movement: &Movement) -> f64 {
+        let mut velocity = DVec3::X * movement.max_speed;
+        for _ in 0..100 {
+            let side = velocity.with_y(0.0).normalize().cross(DVec3::Y);
+            movement.accelerate(&mut velocity, side, movement.max_speed, true);
+        }
+        velocity.with_y(0.0).length()
+    }
+
+    #[test]
+    fn strafe_jumping_gains_speed_only_when_enabled() {
+        let mut movement = Movement {
+            strafe_jumping: true,
+            ..Default::default()
+        };
+        assert!(strafe(&movement) > movement.max_speed * 1.1);
+
+        movement.strafe_jumping = false;
+        assert!(strafe(&movement) <= movement.max_speed + f64::EPSILON);
+    }
+
+    #[test]
+    fn jumping_caps_speed_without_bunny_hopping() {
+        let mut movement = Movement {
+            bunny_hopping: false,
+            ..Default::default()
+        };
+        let fast = DVec3::X * movement.max_speed * 2.0;
+
+        let mut velocity = fast;
+        movement.jump(&mut velocity);
+        assert!(velocity.with_y(0.0).length() <= movement.max_speed + f64::EPSILON);
+
+        movement.bunny_hopping = true;
+        let mut velocity = fast;
+        movement.jump(&mut velocity);
+        assert_eq!(velocity.with_y(0.0), fast);
+        assert_eq!(velocity.y, movement.jump_velocity);
+    }
+}
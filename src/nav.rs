@@ -0,0 +1,318 @@
+use crate::{
+    consts::*,
+    map::{Trigger, Triggers},
+    player::{grounded, overlaps},
+};
+use macroquad::prelude::*;
+use parry3d_f64::{
+    math::{Isometry, Point, Vector},
+    query::{Ray, RayCast},
+    shape::Compound,
+};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    ops::Range,
+};
+
+/// A node waiting to be expanded, ordered so that the heap pops the lowest estimate first.
+struct Open {
+    estimate: f64,
+    node: usize,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+/// Where bots can walk: a grid of `NAV_CELL` cells over the floor, each with a node for every
+/// surface in it a player can stand on, linked to the nodes of the cells around it that can be
+/// walked to.
+pub struct Nav {
+    /// Corner of the first cell, as `(x, z)`.
+    min: DVec2,
+    /// Cells along x and z.
+    size: [usize; 2],
+    /// Nodes of each cell, indices into `nodes`.
+    cells: Vec<Range<usize>>,
+    /// Player positions.
+    nodes: Vec<DVec3>,
+    links: Vec<Vec<usize>>,
+}
+
+impl Nav {
+    /// Over the floor of `floor_size`, keeping out of kill volumes, jump pads and teleporters.
+    pub fn new(compound: &Compound, triggers: &Triggers, floor_size: f64) -> Self {
+        let count = (floor_size * 2.0 / NAV_CELL).floor() as usize;
+        let min = DVec2::splat(-floor_size);
+        let top = compound.local_aabb().maxs.y.max(0.0) + PLAYER_SIZE.y * 2.0;
+        let down =
+            |x: f64, y: f64, z: f64| Ray::new(Point::new(x, y, z), Vector::new(0.0, -1.0, 0.0));
+
+        let standable = |position: DVec3| {
+            !overlaps(compound, position)
+                && grounded(compound, position)
+                && triggers.touching(position).iter().all(|&index| {
+                    matches!(
+                        triggers.triggers[index],
+                        Trigger::Ladder | Trigger::Event { .. }
+                    )
+                })
+        };
+
+        let mut cells = Vec::with_capacity(count * count);
+        let mut nodes = Vec::new();
+        for row in 0..count {
+            for column in 0..count {
+                let (x, z) = (
+                    min.x + (column as f64 + 0.5) * NAV_CELL,
+                    min.y + (row as f64 + 0.5) * NAV_CELL,
+                );
+                let start = nodes.len();
+
+                // Every surface from the top down, through whatever is in the way
+                let mut y = top;
+                while y > 0.0 {
+                    let toi = compound
+                        .cast_ray(&Isometry::identity(), &down(x, y, z), y, true)
+                        .unwrap_or(y);
+                    y -= toi;
+                    let position = dvec3(x, y + PLAYER_SIZE.y, z);
+                    if toi > 0.0 && standable(position) {
+                        nodes.push(position);
+                    }
+
+                    let through = compound
+                        .cast_ray(
+                            &Isometry::identity(),
+                            &down(x, y - GROUND_PROBE, z),
+                            y,
+                            false,
+                        )
+                        .unwrap_or(y);
+                    y -= through + GROUND_PROBE * 2.0;
+                }
+
+                // Bottom up, so that the floor comes first
+                nodes[start..].reverse();
+                cells.push(start..nodes.len());
+            }
+        }
+
+        let mut nav = Self {
+            min,
+            size: [count, count],
+            cells,
+            nodes,
+            links: Vec::new(),
+        };
+
+        nav.links = (0..nav.nodes.len())
+            .map(|node| {
+                let from = nav.nodes[node];
+                nav.around(from)
+                    .filter(|&other| {
+                        let to = nav.nodes[other];
+                        // Through the middle at the higher end, which catches walls and corners
+                        let middle = ((from + to) / 2.0).with_y(from.y.max(to.y));
+                        other != node
+                            && (-NAV_DROP..=STEP_HEIGHT).contains(&(to.y - from.y))
+                            && !overlaps(compound, middle)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        nav
+    }
+
+    fn cell(&self, position: DVec3) -> Option<[usize; 2]> {
+        let offset = (position.xz() - self.min) / NAV_CELL;
+        (offset.x >= 0.0
+            && offset.y >= 0.0
+            && (offset.x as usize) < self.size[0]
+            && (offset.y as usize) < self.size[1])
+            .then_some([offset.x as usize, offset.y as usize])
+    }
+
+    /// Nodes of the cell of `position` and of the cells around it.
+    fn around(&self, position: DVec3) -> impl Iterator<Item = usize> + '_ {
+        let cell = self.cell(position);
+        (-1..=1)
+            .flat_map(|row| (-1..=1).map(move |column| (row, column)))
+            .filter_map(move |(row, column): (isize, isize)| {
+                let [x, z] = cell?;
+                let (x, z) = (x.checked_add_signed(column)?, z.checked_add_signed(row)?);
+                (x < self.size[0] && z < self.size[1])
+                    .then(|| self.cells[z * self.size[0] + x].clone())
+            })
+            .flatten()
+    }
+
+    /// The node closest to `position`, if there's any around it.
+    fn nearest(&self, position: DVec3) -> Option<usize> {
+        self.around(position).min_by(|&a, &b| {
+            self.nodes[a]
+                .distance_squared(position)
+                .total_cmp(&self.nodes[b].distance_squared(position))
+        })
+    }
+
+    /// Waypoints from `from` to `to` by A*, starting with the node at `from`. `None` if either
+    /// is off the grid or there's no way between them.
+    pub fn path(&self, from: DVec3, to: DVec3) -> Option<Vec<DVec3>> {
+        let (start, goal) = (self.nearest(from)?, self.nearest(to)?);
+        let goal_position = self.nodes[goal];
+
+        let mut open = BinaryHeap::from([Open {
+            estimate: self.nodes[start].distance(goal_position),
+            node: start,
+        }]);
+        let mut costs = HashMap::from([(start, 0.0)]);
+        let mut came_from = HashMap::new();
+
+        while let Some(Open { node, .. }) = open.pop() {
+            if node == goal {
+                let mut path = vec![self.nodes[goal]];
+                let mut node = goal;
+                while let Some(&previous) = came_from.get(&node) {
+                    path.push(self.nodes[previous]);
+                    node = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let cost = costs[&node];
+            for &next in &self.links[node] {
+                let next_cost = cost + self.nodes[node].distance(self.nodes[next]);
+                if costs.get(&next).is_none_or(|&known| next_cost < known) {
+                    costs.insert(next, next_cost);
+                    came_from.insert(next, node);
+                    open.push(Open {
+                        estimate: next_cost + self.nodes[next].distance(goal_position),
+                        node: next,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::isometry;
+    use parry3d_f64::shape::SharedShape;
+
+    fn compound(boxes: &[(DVec3, DVec3)]) -> Compound {
+        Compound::new(
+            boxes
+                .iter()
+                .map(|(center, half_extents)| {
+                    (
+                        isometry(*center, DQuat::IDENTITY),
+                        SharedShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn no_triggers() -> Triggers {
+        Triggers::new(Vec::new(), Vec::new())
+    }
+
+    #[test]
+    fn walks_around_walls() {
+        // Along z from -4 to 4 on a floor of 6
+        let compound = compound(&[(dvec3(0.0, 1.0, 0.0), dvec3(0.2, 1.0, 4.0))]);
+        let nav = Nav::new(&compound, &no_triggers(), 6.0);
+
+        let from = dvec3(-2.0, PLAYER_SIZE.y, 0.0);
+        let to = dvec3(2.0, PLAYER_SIZE.y, 0.0);
+        let path = nav.path(from, to).unwrap();
+        assert!(path.first().unwrap().distance(from) < NAV_CELL);
+        assert!(path.last().unwrap().distance(to) < NAV_CELL);
+        assert!(path.iter().any(|waypoint| waypoint.z.abs() > 4.0));
+        for pair in path.windows(2) {
+            assert!(pair[0].distance(pair[1]) < NAV_CELL * 1.5);
+        }
+    }
+
+    #[test]
+    fn stands_on_and_under_platforms() {
+        // A platform high enough to walk under, with a step up to the crate next to it
+        let compound = compound(&[
+            (dvec3(0.0, 1.8, 0.0), dvec3(1.0, 0.1, 1.0)),
+            (dvec3(3.0, 0.1, 0.0), dvec3(1.0, 0.1, 1.0)),
+        ]);
+        let nav = Nav::new(&compound, &no_triggers(), 6.0);
+
+        let cell = nav.cell(DVec3::ZERO).unwrap();
+        let heights = nav.cells[cell[1] * nav.size[0] + cell[0]]
+            .clone()
+            .map(|node| nav.nodes[node].y)
+            .collect::<Vec<_>>();
+        assert_eq!(heights.len(), 2);
+        assert!((heights[0] - PLAYER_SIZE.y).abs() < 1e-9);
+        assert!((heights[1] - (1.9 + PLAYER_SIZE.y)).abs() < 1e-9);
+
+        // Stepped onto, but the platform can't be reached from the floor
+        let crate_top = dvec3(3.0, 0.2 + PLAYER_SIZE.y, 0.0);
+        assert!(
+            nav.path(dvec3(-3.0, PLAYER_SIZE.y, 0.0), crate_top)
+                .is_some()
+        );
+        assert!(
+            nav.path(
+                dvec3(-3.0, PLAYER_SIZE.y, 0.0),
+                dvec3(0.0, 1.9 + PLAYER_SIZE.y, 0.0)
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn keeps_out_of_kill_volumes() {
+        // A kill volume across the whole floor but for a way around at z > 4
+        let compound = compound(&[(dvec3(0.0, -1.0, 0.0), dvec3(0.1, 0.1, 0.1))]);
+        let triggers = Triggers::new(
+            vec![(
+                isometry(dvec3(0.0, 0.5, -1.0), DQuat::IDENTITY),
+                SharedShape::cuboid(0.5, 0.5, 5.0),
+            )],
+            vec![Trigger::Kill],
+        );
+        let nav = Nav::new(&compound, &triggers, 6.0);
+
+        let path = nav
+            .path(
+                dvec3(-2.0, PLAYER_SIZE.y, 0.0),
+                dvec3(2.0, PLAYER_SIZE.y, 0.0),
+            )
+            .unwrap();
+        assert!(
+            path.iter()
+                .all(|waypoint| waypoint.z > 4.0 || waypoint.x.abs() > 0.5)
+        );
+    }
+}
@@ -1,16 +1,24 @@
-use crate::consts::*;
-use ::rand::{Rng, rngs::StdRng};
-use macroquad::{
-    audio::{Sound, play_sound_once},
-    prelude::*,
+use crate::{
+    audio::Cue,
+    consts::*,
+    hitboxes::Hitboxes,
+    map::{Trigger, Triggers},
+    model::{Part, Pose},
+    movement::Movement,
+    round::Team,
+    weapon::{Slot, Weapon},
 };
+use ::rand::{Rng, SeedableRng, rngs::StdRng};
+use bincode::{Decode, Encode};
+use macroquad::prelude::*;
 use parry3d_f64::{
     math::{Isometry, Point, Vector},
-    query::{Ray, RayCast, contact},
+    query::{Ray, RayCast, ShapeCastOptions, cast_shapes, contact},
     shape::{Compound, Cuboid},
 };
 use std::{
     collections::HashMap,
+    mem,
     net::SocketAddr,
     sync::{Arc, RwLock},
     time::Instant,
@@ -20,7 +28,23 @@ use std::{
 pub struct Player {
     pub crouched: bool,
     pub walking: bool,
-    pub jump: Option<f64>,
+    /// Per frame.
+    pub velocity: DVec3,
+    pub airborne: bool,
+    /// Fastest downwards speed since leaving the ground.
+    pub fall_speed: f64,
+    /// When the player last landed hard enough to be slowed down.
+    pub hard_landing_timestamp: Option<Instant>,
+    /// Damage taken from the map this frame, to be sent to the peers.
+    pub hurt: f64,
+    /// Can't be hurt by other players, sent along with the position.
+    pub protected: bool,
+    /// When the spawn protection of the local player started.
+    pub spawn_timestamp: Option<Instant>,
+    /// Indices of the map triggers the player is inside of.
+    pub touching: Vec<usize>,
+    /// Names of the map events the player fired this frame.
+    pub fired: Vec<String>,
     pub yaw: f64,
     pub pitch: f64,
     pub front: DVec3,
@@ -29,16 +53,89 @@ pub struct Player {
     pub position: DVec3,
     pub last_bullet_timestamp: Option<Instant>,
     pub last_move_timestamp: Option<Instant>,
-    pub bullets_since_last_reload: u8,
-    pub last_reload_timestamp: Option<Instant>,
+    /// When the reload in progress started.
+    pub reload_timestamp: Option<Instant>,
     pub mouse_position: DVec2,
+    /// Index of the next shot within the current burst.
+    pub shot: usize,
+    /// `(yaw, pitch)` the view was kicked by and has not yet recovered from.
+    pub recoil: DVec2,
     pub ticks: Vec<Option<DVec3>>,
     pub last_tick_timestamp: Instant,
     pub killed: bool,
+    pub team: Team,
+    pub money: u32,
+    pub health: f64,
+    /// Index into the weapon list of the weapon in hand.
+    pub weapon: usize,
+    /// Only filled for the local player.
+    pub slots: Vec<Slot>,
+    /// Distance covered on the ground since the last footstep.
+    pub stride: f64,
+    /// Sounds the local player made this frame.
+    pub cues: Vec<Cue>,
+    pub pose: Pose,
+}
+
+/// What a player is told to do for a frame, by the keyboard and mouse or by a bot.
+#[derive(Clone, Copy, Default)]
+pub struct Controls {
+    /// Along `front`, from -1 to 1.
+    pub forward: f64,
+    /// Along `right`, from -1 to 1.
+    pub strafe: f64,
+    pub jump: bool,
+    pub crouch: bool,
+    /// Toggles walking.
+    pub walk: bool,
+    /// The trigger is held.
+    pub fire: bool,
+    /// The trigger was pulled this frame.
+    pub fire_pressed: bool,
+    pub reload: bool,
+}
+
+impl Controls {
+    pub fn read() -> Self {
+        let axis = |positive, negative| is_key_down(positive) as i32 - is_key_down(negative) as i32;
+        Self {
+            forward: axis(KeyCode::W, KeyCode::S) as f64,
+            strafe: axis(KeyCode::D, KeyCode::A) as f64,
+            jump: is_key_pressed(KeyCode::Space),
+            crouch: is_key_down(KeyCode::LeftControl),
+            walk: is_key_pressed(KeyCode::LeftShift),
+            fire: is_mouse_button_down(MouseButton::Left),
+            fire_pressed: is_mouse_button_pressed(MouseButton::Left),
+            reload: is_key_pressed(KeyCode::R),
+        }
+    }
+}
+
+/// Damage dealt to a peer, sent to everyone so that they all agree on its health.
+#[derive(Clone, Copy, Encode, Decode)]
+pub struct Hit {
+    pub victim: SocketAddr,
+    pub damage: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Encode, Decode)]
+pub enum Surface {
+    /// Nothing was hit within `BULLET_RANGE`.
+    Air,
+    Wall,
+    Player,
+}
+
+/// Where the pellets of a shot ended up and whom they hurt.
+#[derive(Clone, Encode, Decode)]
+pub struct Shot {
+    pub origin: [f64; 3],
+    pub pellets: Vec<([f64; 3], Surface)>,
+    pub hits: Vec<Hit>,
 }
 
 impl Player {
-    pub fn new(position: DVec3) -> Self {
+    pub fn new(position: DVec3, team: Team) -> Self {
         let yaw: f64 = 0.0;
         let pitch: f64 = 0.0;
         let front = dvec3(
@@ -53,7 +150,15 @@ impl Player {
         Self {
             crouched: false,
             walking: false,
-            jump: None,
+            velocity: DVec3::ZERO,
+            airborne: false,
+            fall_speed: 0.0,
+            hard_landing_timestamp: None,
+            hurt: 0.0,
+            protected: false,
+            spawn_timestamp: None,
+            touching: Vec::new(),
+            fired: Vec::new(),
             yaw,
             pitch,
             front,
@@ -62,39 +167,261 @@ impl Player {
             position,
             last_bullet_timestamp: None,
             last_move_timestamp: None,
-            bullets_since_last_reload: 0,
-            last_reload_timestamp: None,
+            reload_timestamp: None,
             mouse_position: DVec2::ZERO,
-            ticks: Vec::with_capacity(TICKS_PER_SECOND as usize),
+            shot: 0,
+            recoil: DVec2::ZERO,
+            ticks: Vec::with_capacity(TICKS_PER_SECOND),
             last_tick_timestamp: Instant::now(),
             killed: false,
+            team,
+            money: START_MONEY,
+            health: MAX_HEALTH,
+            weapon: 0,
+            slots: Vec::new(),
+            stride: 0.0,
+            cues: Vec::new(),
+            pose: Pose::Idle,
         }
     }
 
-    pub fn movement(&mut self, compound: &Compound) -> bool {
-        let mut moved = false;
+    /// Takes away everything bought and hands out the default weapons.
+    pub fn equip_defaults(&mut self, weapons: &[Weapon]) {
+        self.slots = weapons
+            .iter()
+            .enumerate()
+            .filter(|(_, weapon)| weapon.default)
+            .map(|(index, _)| Slot::new(index, weapons))
+            .collect();
+        self.weapon = self.slots.first().map_or(0, |slot| slot.weapon);
+    }
 
-        // Walking toggle
-        if is_key_pressed(KeyCode::LeftShift) {
-            self.walking = !self.walking;
+    pub fn slot(&self) -> &Slot {
+        self.slots
+            .iter()
+            .find(|slot| slot.weapon == self.weapon)
+            .unwrap()
+    }
+
+    pub fn slot_mut(&mut self) -> &mut Slot {
+        self.slots
+            .iter_mut()
+            .find(|slot| slot.weapon == self.weapon)
+            .unwrap()
+    }
+
+    /// Returns whether the player owns the weapon.
+    pub fn buy(&mut self, weapon: usize, weapons: &[Weapon]) -> bool {
+        if self.slots.iter().any(|slot| slot.weapon == weapon) {
+            return true;
+        }
+        if self.money < weapons[weapon].price {
+            return false;
         }
 
-        // Space
-        let just_jumped = is_key_pressed(KeyCode::Space) && !self.crouched;
-        if just_jumped && self.jump.is_none() {
-            self.jump = Some(-JUMP_VELOCITY);
-            if self.last_move_timestamp.is_none() {
-                self.last_move_timestamp = Some(Instant::now());
-            }
+        self.money -= weapons[weapon].price;
+        self.slots.push(Slot::new(weapon, weapons));
+        self.weapon = weapon;
+        true
+    }
+
+    pub fn switching(&mut self) {
+        let current = self
+            .slots
+            .iter()
+            .position(|slot| slot.weapon == self.weapon)
+            .unwrap();
+
+        let keys = [
+            KeyCode::Key1,
+            KeyCode::Key2,
+            KeyCode::Key3,
+            KeyCode::Key4,
+            KeyCode::Key5,
+            KeyCode::Key6,
+            KeyCode::Key7,
+            KeyCode::Key8,
+            KeyCode::Key9,
+        ];
+        let mut selected = keys
+            .iter()
+            .position(|key| is_key_pressed(*key))
+            .filter(|&index| index < self.slots.len())
+            .unwrap_or(current);
+
+        let wheel = mouse_wheel().1;
+        if wheel > 0.0 {
+            selected = (current + self.slots.len() - 1) % self.slots.len();
+        } else if wheel < 0.0 {
+            selected = (current + 1) % self.slots.len();
+        }
+
+        self.select(selected);
+    }
+
+    /// Takes out the weapon in the slot, which cancels any reload.
+    fn select(&mut self, selected: usize) {
+        let previous = self.weapon;
+        self.weapon = self.slots[selected].weapon;
+        if self.weapon != previous {
+            self.shot = 0;
+            self.reload_timestamp = None;
+        }
+    }
+
+    /// Returns whether a reload has started, which it doesn't with a full magazine or no reserve.
+    pub fn start_reload(&mut self) -> bool {
+        let slot = self.slot();
+        if self.reload_timestamp.is_some()
+            || slot.bullets_since_last_reload == 0
+            || slot.reserve == 0
+        {
+            return false;
+        }
+
+        self.reload_timestamp = Some(Instant::now());
+        self.cues.push(Cue::Reload);
+        true
+    }
+
+    /// Starts a reload when told to and tops the magazine up from the reserve once it's done.
+    pub fn reloading(&mut self, controls: &Controls, weapons: &[Weapon]) {
+        if controls.reload {
+            self.start_reload();
+        }
+
+        if self
+            .reload_progress(weapons)
+            .is_some_and(|progress| progress >= 1.0)
+        {
+            self.reload_timestamp = None;
+
+            let slot = self.slot_mut();
+            let refill = (slot.bullets_since_last_reload as u16).min(slot.reserve);
+            slot.bullets_since_last_reload -= refill as u8;
+            slot.reserve -= refill;
+        }
+    }
+
+    /// From 0 to 1 while reloading.
+    pub fn reload_progress(&self, weapons: &[Weapon]) -> Option<f64> {
+        self.reload_timestamp.map(|reload_timestamp| {
+            (reload_timestamp.elapsed().as_secs_f64()
+                / weapons[self.weapon].reload_duration.as_secs_f64())
+            .min(1.0)
+        })
+    }
+
+    /// Returns whether the damage killed the player.
+    pub fn damage(&mut self, amount: f64) -> bool {
+        if self.killed {
+            return false;
+        }
+
+        self.health -= amount;
+        self.killed = self.health <= 0.0;
+        if self.killed {
+            self.pose = Pose::Death;
+        }
+        self.killed
+    }
+
+    /// Where the camera is and the bullets come from, which lines up with the model's head.
+    pub fn eye(&self) -> DVec3 {
+        self.position
+            + DVec3::Y
+                * if self.crouched {
+                    EYE_OFFSET - CROUCH_LEVEL_CONST
+                } else {
+                    EYE_OFFSET
+                }
+    }
+
+    pub fn update_pose(&mut self, moved: bool) {
+        self.pose = if self.killed {
+            Pose::Death
+        } else if self.airborne {
+            Pose::Jump
+        } else if self.crouched {
+            Pose::Crouch
+        } else if moved {
+            Pose::Run
+        } else {
+            Pose::Idle
+        };
+    }
+
+    /// Puts the player back into play at the start of a round.
+    pub fn respawn(&mut self, position: DVec3, weapons: &[Weapon]) {
+        if self.killed {
+            self.equip_defaults(weapons);
+        }
+        for slot in &mut self.slots {
+            *slot = Slot::new(slot.weapon, weapons);
+        }
+
+        self.position = position;
+        self.velocity = DVec3::ZERO;
+        self.airborne = false;
+        self.fall_speed = 0.0;
+        self.hard_landing_timestamp = None;
+        self.hurt = 0.0;
+        self.touching.clear();
+        self.protect();
+        self.killed = false;
+        self.crouched = false;
+        self.pose = Pose::Idle;
+        self.health = MAX_HEALTH;
+        self.ticks.clear();
+        self.reload_timestamp = None;
+        self.last_bullet_timestamp = None;
+        self.shot = 0;
+        self.recoil = DVec2::ZERO;
+        self.last_move_timestamp = None;
+    }
+
+    pub fn protect(&mut self) {
+        self.protected = true;
+        self.spawn_timestamp = Some(Instant::now());
+    }
+
+    /// Ends spawn protection once it has run out. Only for the local player, peers say
+    /// themselves whether they're protected.
+    pub fn update_protection(&mut self) {
+        if self
+            .spawn_timestamp
+            .is_some_and(|timestamp| timestamp.elapsed() >= SPAWN_PROTECTION_DURATION)
+        {
+            self.protected = false;
+            self.spawn_timestamp = None;
+        }
+    }
+
+    pub fn earn(&mut self, amount: u32) {
+        self.money = (self.money + amount).min(MAX_MONEY);
+    }
+
+    /// Returns whether the player moves fast enough to be inaccurate.
+    pub fn movement(
+        &mut self,
+        controls: &Controls,
+        compound: &Compound,
+        triggers: &Triggers,
+        movement: &Movement,
+    ) -> bool {
+        if controls.walk {
+            self.walking = !self.walking;
         }
 
-        let self_cuboid = Cuboid::new(PLAYER_SIZE);
+        self.crouched = controls.crouch;
 
         self.front.y = 0.0;
         self.front = self.front.normalize();
 
-        // Movement
-        let move_speed = MOVE_SPEED
+        let direction =
+            (self.front * controls.forward + self.right * controls.strafe).normalize_or_zero();
+
+        let mut speed = movement.max_speed
             * (if self.crouched {
                 CROUCH_SPEED_CONST
             } else if self.walking {
@@ -102,116 +429,204 @@ impl Player {
             } else {
                 1.0
             });
+        if self
+            .hard_landing_timestamp
+            .is_some_and(|timestamp| timestamp.elapsed() < movement.landing_slowdown_duration)
+        {
+            speed *= movement.landing_slowdown;
+        }
 
-        let mut pos_delta = DVec3::ZERO;
-        if is_key_down(KeyCode::W) {
-            pos_delta += self.front;
-            moved = true;
+        // Before friction so that jumping on landing keeps the speed
+        if controls.jump && !self.crouched && !self.airborne {
+            movement.jump(&mut self.velocity);
+            self.airborne = true;
+            self.cues.push(Cue::Jump);
+            if self.last_move_timestamp.is_none() {
+                self.last_move_timestamp = Some(Instant::now());
+            }
         }
-        if is_key_down(KeyCode::S) {
-            pos_delta -= self.front;
-            moved = true;
+
+        // Ladders hold the player up and climb by moving forward and back
+        let climbing = self
+            .touching
+            .iter()
+            .any(|&index| matches!(triggers.triggers[index], Trigger::Ladder));
+        if climbing {
+            self.velocity.y = controls.forward.clamp(-1.0, 1.0) * movement.ladder_speed;
+            self.fall_speed = 0.0;
         }
-        if is_key_down(KeyCode::A) {
-            pos_delta -= self.right;
-            moved = true;
+
+        if !self.airborne || climbing {
+            movement.friction(&mut self.velocity);
         }
-        if is_key_down(KeyCode::D) {
-            pos_delta += self.right;
-            moved = true;
+        movement.accelerate(
+            &mut self.velocity,
+            direction,
+            speed,
+            self.airborne && !climbing,
+        );
+
+        self.collide(compound, movement);
+        self.trigger(triggers);
+
+        let moved =
+            self.velocity.with_y(0.0).length() > movement.max_speed * movement.accurate_speed;
+        if moved && self.last_move_timestamp.is_none() {
+            self.last_move_timestamp = Some(Instant::now());
+        } else if !moved && !self.airborne {
+            self.last_move_timestamp = None;
         }
 
-        if pos_delta.length() > 0.0 {
-            pos_delta = pos_delta.normalize();
+        moved
+    }
+
+    /// Moves by the velocity, sliding along walls, stepping onto ledges and following ramps.
+    pub fn collide(&mut self, compound: &Compound, movement: &Movement) {
+        let on_ground = !self.airborne;
+
+        // Up and down first, then along the floor
+        let (vertical, vertical_touch) = sweep(compound, self.position, DVec3::Y * self.velocity.y);
+        let step = self.velocity.with_y(0.0);
+        let (mut position, touch) = sweep(compound, vertical, step);
+
+        // Climb onto ledges no taller than the step height rather than stopping at them
+        if on_ground && touch.wall {
+            let raised = vertical + DVec3::Y * STEP_HEIGHT;
+            if !overlaps(compound, raised) {
+                let (stepped, _) = sweep(compound, raised, step);
+                let stepped = settle(compound, stepped, STEP_HEIGHT);
+                let progress = |to: DVec3| (to - vertical).with_y(0.0).length();
+                if progress(stepped) > progress(position) + f64::EPSILON
+                    && grounded(compound, stepped)
+                {
+                    position = stepped;
+                }
+            }
         }
 
-        let position = self.position + pos_delta * move_speed;
+        // Stick to the ground when walking down ramps and off low ledges
+        if on_ground && self.velocity.y <= 0.0 && !grounded(compound, position) {
+            let snapped = settle(compound, position, STEP_HEIGHT);
+            if grounded(compound, snapped) {
+                position = snapped;
+            }
+        }
 
-        // Collisions
-        let maybe_contact = contact(
-            &Isometry::identity(),
-            compound,
-            &Isometry::translation(self.position.x, self.position.y, self.position.z),
-            &self_cuboid,
-            0.0,
-        )
-        .unwrap();
-        let mut y_intersection =
-            !just_jumped && maybe_contact.is_some_and(|contact| self.position.y > contact.point2.y);
+        // Walls take away the speed going into them
+        if touch.wall {
+            let moved = (position - vertical).with_y(0.0);
+            if moved.length() < step.length() {
+                self.velocity = moved.with_y(self.velocity.y);
+            }
+        }
 
-        if self.position.y > PLAYER_SIZE.y && !y_intersection && self.jump.is_none() {
-            self.jump = Some(0.0);
+        self.position = position;
+
+        let grounded = grounded(compound, position);
+        if self.airborne {
+            self.fall_speed = self.fall_speed.max(-self.velocity.y);
+            if grounded && self.velocity.y <= 0.0 {
+                self.airborne = false;
+                self.velocity.y = 0.0;
+                self.land(movement);
+            } else {
+                if vertical_touch.ceiling && self.velocity.y > 0.0 {
+                    self.velocity.y = 0.0;
+                }
+                self.velocity.y -= movement.gravity;
+            }
+        } else if !grounded {
+            self.airborne = true;
+            self.velocity.y = 0.0;
             if self.last_move_timestamp.is_none() {
                 self.last_move_timestamp = Some(Instant::now());
             }
         }
 
-        if let Some(jump) = &mut self.jump {
-            if y_intersection {
-                self.position.y = maybe_contact.unwrap().point1.y as f64 + PLAYER_SIZE.y;
-                self.jump = None;
-            } else if !just_jumped && self.position.y <= PLAYER_SIZE.y && maybe_contact.is_none() {
-                self.position.y = PLAYER_SIZE.y;
-                self.jump = None;
-            } else {
-                if let Some(contact) = maybe_contact {
-                    if self.position.y <= contact.point2.y {
-                        *jump = 0.0;
-                        self.position.y = (contact.point1.y - PLAYER_SIZE.y) * 0.99999;
-                        y_intersection = false;
+        // Rest exactly on the ground rather than anywhere within the probe
+        if !self.airborne {
+            self.position = settle(compound, self.position, GROUND_PROBE);
+        }
+    }
+
+    /// Sets off the triggers the player is inside of after moving.
+    fn trigger(&mut self, triggers: &Triggers) {
+        let touching = triggers.touching(self.position);
+
+        for &index in &touching {
+            let entered = !self.touching.contains(&index);
+            match &triggers.triggers[index] {
+                Trigger::Ladder => {}
+                Trigger::JumpPad { velocity } => {
+                    self.velocity = DVec3::from_array(*velocity);
+                    self.airborne = true;
+                    self.fall_speed = 0.0;
+                    if entered {
+                        self.cues.push(Cue::Jump);
+                    }
+                }
+                Trigger::Teleporter { destination, yaw } => {
+                    self.position = DVec3::from_array(*destination) + DVec3::Y * PLAYER_SIZE.y;
+                    self.velocity = DVec3::ZERO;
+                    self.airborne = true;
+                    self.fall_speed = 0.0;
+                    if let Some(yaw) = yaw {
+                        self.yaw = yaw.to_radians();
+                        self.orient();
+                    }
+                }
+                Trigger::Kill => {
+                    let health = self.health;
+                    if self.damage(health) {
+                        self.hurt += health;
+                        self.cues.push(Cue::Death);
+                    }
+                }
+                Trigger::Event { name } => {
+                    if entered {
+                        self.fired.push(name.clone());
                     }
                 }
-                self.position.y -= *jump;
-                *jump += GRAVITY;
             }
         }
 
-        let x_intersection = contact(
-            &Isometry::identity(),
-            compound,
-            &Isometry::translation(position.x, self.position.y, self.position.z),
-            &self_cuboid,
-            0.0,
-        )
-        .unwrap()
-        .is_some();
-
-        let z_intersection = !x_intersection
-            && contact(
-                &Isometry::identity(),
-                compound,
-                &Isometry::translation(self.position.x, self.position.y, position.z),
-                &self_cuboid,
-                0.0,
-            )
-            .unwrap()
-            .is_some();
+        self.touching = touching;
+    }
 
-        if y_intersection {
-            self.position.x = position.x;
-            self.position.z = position.z;
-        } else {
-            if !x_intersection {
-                self.position.x = position.x
-            }
-            if !z_intersection {
-                self.position.z = position.z
-            };
+    fn land(&mut self, movement: &Movement) {
+        let speed = mem::take(&mut self.fall_speed);
+        if speed < movement.hard_landing_speed {
+            self.cues.push(Cue::Land);
+            return;
         }
 
-        // Reload
-        if is_key_pressed(KeyCode::R) {
-            self.bullets_since_last_reload = 0;
-            self.last_reload_timestamp = Some(Instant::now());
+        self.cues.push(Cue::HardLanding);
+        self.hard_landing_timestamp = Some(Instant::now());
+        // Landing hard takes the speed along the floor too
+        self.velocity *= movement.landing_slowdown;
+
+        let damage = movement.fall_damage(speed);
+        if damage > 0.0 {
+            self.hurt += damage;
+            if self.damage(damage) {
+                self.cues.push(Cue::Death);
+            }
         }
+    }
 
-        if moved && self.last_move_timestamp.is_none() {
-            self.last_move_timestamp = Some(Instant::now());
-        } else if !moved && self.jump.is_none() {
-            self.last_move_timestamp = None;
+    /// Returns whether the player just made a footstep.
+    pub fn footstep(&mut self, previous_position: DVec3) -> bool {
+        if self.airborne {
+            return false;
         }
 
-        moved
+        self.stride += (self.position - previous_position).with_y(0.0).length();
+        if self.stride >= FOOTSTEP_STRIDE {
+            self.stride = 0.0;
+            true
+        } else {
+            false
+        }
     }
 
     pub fn look(&mut self, delta: f64) {
@@ -222,6 +637,11 @@ impl Player {
 
         self.yaw += mouse_delta.x * delta * LOOK_SPEED;
         self.pitch += mouse_delta.y * delta * -LOOK_SPEED;
+        self.orient();
+    }
+
+    /// Recomputes the view vectors from `yaw` and `pitch`.
+    pub fn orient(&mut self) {
         self.pitch = self.pitch.clamp(-PITCH_BOUND, PITCH_BOUND);
         self.front = dvec3(
             self.yaw.cos() * self.pitch.cos(),
@@ -234,79 +654,628 @@ impl Player {
         self.up = self.right.cross(self.front).normalize();
     }
 
+    /// Brings the view back after the recoil kicks and ends the burst once the trigger rests.
+    pub fn recover(&mut self, weapons: &[Weapon], delta: f64) {
+        let weapon = &weapons[self.weapon];
+
+        let step = (weapon.recoil_recovery * delta).min(self.recoil.length());
+        if step > 0.0 {
+            let back = self.recoil.normalize() * step;
+            self.recoil -= back;
+            self.yaw -= back.x;
+            self.pitch -= back.y;
+            self.orient();
+        }
+
+        if self
+            .last_bullet_timestamp
+            .is_none_or(|last_bullet_timestamp| {
+                last_bullet_timestamp.elapsed() > weapon.recoil_reset
+            })
+        {
+            self.shot = 0;
+        }
+    }
+
+    /// Half-angle of the cone the next shot lands in. Crouching keeps it tight, moving and
+    /// jumping open it up the longer the player has been moving.
+    pub fn spread(&self, weapon: &Weapon, moved: bool) -> f64 {
+        let inaccurate = !self.crouched && (self.airborne || moved);
+
+        weapon.pellet_spread
+            + if inaccurate {
+                weapon.spread(
+                    self.last_move_timestamp
+                        .map(|timestamp| timestamp.elapsed())
+                        .unwrap_or_default(),
+                )
+            } else {
+                0.0
+            }
+    }
+
     pub fn bullets(
         &mut self,
+        controls: &Controls,
+        weapons: &[Weapon],
+        compound: &Compound,
         peers: Arc<RwLock<HashMap<SocketAddr, Player>>>,
-        bullet_sound: &Sound,
         moved: bool,
-        rng: &mut StdRng,
-    ) {
-        if is_mouse_button_down(MouseButton::Left)
-            && self.bullets_since_last_reload < BULLETS_BEFORE_RELOAD
-            && self
-                .last_reload_timestamp
-                .is_none_or(|last_reload_timestamp| {
-                    last_reload_timestamp.elapsed() > RELOAD_DURATION
-                })
+    ) -> Option<Shot> {
+        let weapon = &weapons[self.weapon];
+        let trigger = if weapon.automatic {
+            controls.fire
+        } else {
+            controls.fire_pressed
+        };
+
+        if controls.fire_pressed
+            && self.slot().bullets_since_last_reload >= weapon.magazine
+            && !self.start_reload()
+        {
+            self.cues.push(Cue::Empty);
+        }
+
+        if trigger
+            && self.slot().bullets_since_last_reload < weapon.magazine
+            && self.reload_timestamp.is_none()
             && self
                 .last_bullet_timestamp
                 .is_none_or(|last_bullet_timestamp| {
-                    last_bullet_timestamp.elapsed() > BULLET_INTERVAL
+                    last_bullet_timestamp.elapsed() > weapon.fire_interval
                 })
         {
-            self.bullets_since_last_reload += 1;
+            self.slot_mut().bullets_since_last_reload += 1;
+
+            // Shooting gives up spawn protection
+            self.protected = false;
+            self.spawn_timestamp = None;
 
-            let inaccurate = !self.crouched && (self.jump.is_some() || moved);
+            let spread = self.spread(weapon, moved);
             let now = Instant::now();
             self.last_bullet_timestamp = Some(now);
 
-            let spread_level = match self.last_move_timestamp {
-                Some(timestamp) => {
-                    timestamp.elapsed().as_nanos() as f64 / BULLET_SPREAD_PERIOD.as_nanos() as f64
-                }
-                None => 0.0,
-            }
-            .min(1.0);
-
-            let ray = Ray::new(
-                Point::new(self.position.x, self.position.y, self.position.z),
-                Vector::new(
-                    self.front.x
-                        + inaccurate as usize as f64
-                            * rng.random_range(-BULLET_SPREAD..BULLET_SPREAD)
-                            * spread_level,
-                    self.front.y
-                        + inaccurate as usize as f64
-                            * rng.random_range(-BULLET_SPREAD..BULLET_SPREAD)
-                            * spread_level,
-                    self.front.z
-                        + inaccurate as usize as f64
-                            * rng.random_range(-BULLET_SPREAD..BULLET_SPREAD)
-                            * spread_level,
-                ),
-            );
+            let eye = self.eye();
+
+            // Seeded by the shot so that the same spray always lands the same way
+            let mut rng = StdRng::seed_from_u64(((self.weapon as u64) << 32) | self.shot as u64);
+            let rays = (0..weapon.pellets)
+                .map(|_| {
+                    let direction = sample_cone(self.front, spread, &mut rng);
+                    Ray::new(
+                        Point::new(eye.x, eye.y, eye.z),
+                        Vector::new(direction.x, direction.y, direction.z),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let kick = weapon.kick(self.shot);
+            self.recoil += kick;
+            self.yaw += kick.x;
+            self.pitch += kick.y;
+            self.orient();
+            self.shot += 1;
 
             let mut peers_write = peers.write().unwrap();
+            let hitboxes = Hitboxes::new(&peers_write, self.team, get_time());
 
-            for peer in peers_write.values_mut() {
-                for tick in &peer.ticks {
-                    if let Some(position) = tick {
-                        if Cuboid::new(PLAYER_SIZE * 2.0)
-                            .cast_ray(
-                                &Isometry::translation(position.x, position.y, position.z),
-                                &ray,
-                                f64::INFINITY,
-                                true,
-                            )
-                            .is_some()
-                        {
-                            peer.killed = true;
-                        }
-                    }
+            let mut pellets = Vec::with_capacity(rays.len());
+            let mut damages = HashMap::<SocketAddr, f64>::new();
+            for ray in &rays {
+                let mut end = compound
+                    .cast_ray(&Isometry::identity(), ray, BULLET_RANGE, true)
+                    .map_or((BULLET_RANGE, Surface::Air), |toi| (toi, Surface::Wall));
+                let mut victim = None;
+                if let Some((toi, peer_host, part)) = hitboxes.cast_ray(ray, end.0) {
+                    end = (toi, Surface::Player);
+                    victim = Some((peer_host, part == Part::Head));
                 }
+
+                if let Some((victim, headshot)) = victim {
+                    *damages.entry(victim).or_default() +=
+                        weapon.damage * if headshot { HEADSHOT_MULTIPLIER } else { 1.0 };
+                }
+                let point = ray.point_at(end.0);
+                pellets.push(([point.x, point.y, point.z], end.1));
+            }
+
+            let mut hits = Vec::with_capacity(damages.len());
+            for (victim, damage) in damages {
+                if peers_write.get_mut(&victim).unwrap().damage(damage) {
+                    self.earn(KILL_REWARD);
+                }
+                hits.push(Hit { victim, damage });
+            }
+
+            self.cues.push(Cue::Gunshot);
+
+            return Some(Shot {
+                origin: [eye.x, eye.y, eye.z],
+                pellets,
+                hits,
+            });
+        }
+
+        None
+    }
+}
+
+fn translation(position: DVec3) -> Isometry<f64> {
+    Isometry::translation(position.x, position.y, position.z)
+}
+
+/// Which ways the map pushed the player.
+#[derive(Default, Clone, Copy)]
+struct Touch {
+    ground: bool,
+    wall: bool,
+    ceiling: bool,
+}
+
+impl Touch {
+    fn merge(self, other: Self) -> Self {
+        Self {
+            ground: self.ground || other.ground,
+            wall: self.wall || other.wall,
+            ceiling: self.ceiling || other.ceiling,
+        }
+    }
+}
+
+/// Whether a player at `position` is stuck in the map.
+pub fn overlaps(compound: &Compound, position: DVec3) -> bool {
+    contact(
+        &Isometry::identity(),
+        compound,
+        &translation(position),
+        &Cuboid::new(PLAYER_SIZE),
+        0.0,
+    )
+    .unwrap()
+    .is_some_and(|contact| contact.dist < 0.0)
+}
+
+/// Moves a player at `position` out of the map. Walkable surfaces push straight up so that ramps
+/// are climbed at full speed, and walls push only sideways so that the player slides along them.
+fn depenetrate(compound: &Compound, mut position: DVec3) -> (DVec3, Touch) {
+    let walkable = MAX_SLOPE.cos();
+    let mut touch = Touch::default();
+
+    for _ in 0..COLLISION_ITERATIONS {
+        let Some(contact) = contact(
+            &Isometry::identity(),
+            compound,
+            &translation(position),
+            &Cuboid::new(PLAYER_SIZE),
+            0.0,
+        )
+        .unwrap() else {
+            break;
+        };
+        let depth = -contact.dist;
+        if depth <= 0.0 {
+            break;
+        }
+
+        // Out of the map towards the player
+        let normal = dvec3(contact.normal1.x, contact.normal1.y, contact.normal1.z);
+        if normal.y >= walkable {
+            position.y += depth / normal.y;
+            touch.ground = true;
+        } else if normal.y <= -walkable {
+            position += normal * depth;
+            touch.ceiling = true;
+        } else {
+            let sideways = normal.with_y(0.0);
+            position += sideways.normalize() * depth / sideways.length();
+            touch.wall = true;
+        }
+    }
+
+    // The floor isn't part of the map
+    if position.y <= PLAYER_SIZE.y {
+        position.y = PLAYER_SIZE.y;
+        touch.ground = true;
+    }
+
+    (position, touch)
+}
+
+/// Moves a player at `position` by `motion`, stopping at the first surface in the way however
+/// fast the move is, then carrying on along it the same way `depenetrate` pushes out of it.
+fn sweep(compound: &Compound, mut position: DVec3, mut motion: DVec3) -> (DVec3, Touch) {
+    let walkable = MAX_SLOPE.cos();
+    let mut touch = Touch::default();
+    // Slightly smaller so that surfaces being stood on or slid along aren't hit
+    let shape = Cuboid::new(PLAYER_SIZE.add_scalar(-SWEEP_SKIN));
+
+    for _ in 0..COLLISION_ITERATIONS {
+        if motion == DVec3::ZERO {
+            break;
+        }
+
+        let Some(hit) = cast_shapes(
+            &Isometry::identity(),
+            &Vector::zeros(),
+            compound,
+            &translation(position),
+            &Vector::new(motion.x, motion.y, motion.z),
+            &shape,
+            ShapeCastOptions {
+                max_time_of_impact: 1.0,
+                stop_at_penetration: false,
+                compute_impact_geometry_on_penetration: true,
+                ..Default::default()
+            },
+        )
+        .unwrap() else {
+            position += motion;
+            break;
+        };
+
+        position += motion * hit.time_of_impact;
+        motion *= 1.0 - hit.time_of_impact;
+
+        // Out of the map towards the player
+        let normal = dvec3(hit.normal1.x, hit.normal1.y, hit.normal1.z);
+        let into = motion.dot(normal);
+        if into >= 0.0 {
+            position += motion;
+            break;
+        }
+
+        // Only what goes into the surface is taken away
+        if normal.y >= walkable {
+            motion.y -= into / normal.y;
+            touch.ground = true;
+        } else if normal.y <= -walkable {
+            motion -= normal * into;
+            touch.ceiling = true;
+        } else {
+            let sideways = normal.with_y(0.0);
+            motion -= sideways * into / sideways.length_squared();
+            touch.wall = true;
+        }
+    }
+
+    let (position, pushed) = depenetrate(compound, position);
+    (position, touch.merge(pushed))
+}
+
+/// Lowers the player by up to `distance` until it rests on something.
+fn settle(compound: &Compound, position: DVec3, distance: f64) -> DVec3 {
+    let distance = distance.min(position.y - PLAYER_SIZE.y).max(0.0);
+    if !overlaps(compound, position - DVec3::Y * distance) {
+        return position - DVec3::Y * distance;
+    }
+
+    // Bisect between a drop known to be free and one known to overlap
+    let (mut free, mut blocked) = (0.0, distance);
+    for _ in 0..SETTLE_ITERATIONS {
+        let middle = (free + blocked) / 2.0;
+        if overlaps(compound, position - DVec3::Y * middle) {
+            blocked = middle;
+        } else {
+            free = middle;
+        }
+    }
+    position - DVec3::Y * free
+}
+
+/// Whether the player stands on the floor or on a surface that isn't too steep. Only the soles
+/// are tested so that touching a wall doesn't count.
+pub fn grounded(compound: &Compound, position: DVec3) -> bool {
+    if position.y <= PLAYER_SIZE.y + GROUND_PROBE {
+        return true;
+    }
+
+    let soles = Cuboid::new(Vector::new(
+        PLAYER_SIZE.x * 0.9,
+        GROUND_PROBE,
+        PLAYER_SIZE.z * 0.9,
+    ));
+    contact(
+        &Isometry::identity(),
+        compound,
+        &translation(position - DVec3::Y * PLAYER_SIZE.y),
+        &soles,
+        0.0,
+    )
+    .unwrap()
+    .is_some_and(|contact| contact.normal1.y >= MAX_SLOPE.cos())
+}
+
+/// Uniformly picks a direction within `angle` radians of `direction`.
+fn sample_cone(direction: DVec3, angle: f64, rng: &mut StdRng) -> DVec3 {
+    if angle <= 0.0 {
+        return direction;
+    }
+
+    let cos = 1.0 - rng.random::<f64>() * (1.0 - angle.cos());
+    let sin = (1.0 - cos * cos).sqrt();
+    let around = rng.random_range(0.0..std::f64::consts::TAU);
+    let (a, b) = direction.any_orthonormal_pair();
+
+    direction * cos + (a * around.cos() + b * around.sin()) * sin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::isometry;
+    use parry3d_f64::shape::SharedShape;
+
+    /// The floor and a box.
+    fn map(center: DVec3, half_extents: DVec3) -> Compound {
+        Compound::new(vec![(
+            isometry(center, DQuat::IDENTITY),
+            SharedShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
+        )])
+    }
+
+    /// A ramp rising along x at `angle` radians, its foot on the floor at x = 1.
+    fn ramp(angle: f64) -> Compound {
+        Compound::new(vec![(
+            isometry(
+                dvec3(1.0 + 5.0 * angle.cos(), 5.0 * angle.sin(), 0.0),
+                DQuat::from_rotation_z(angle),
+            ),
+            SharedShape::cuboid(5.0, 0.01, 2.0),
+        )])
+    }
+
+    /// Lets a player fall from `height` at `speed` per frame, returning where it lands.
+    fn drop(compound: &Compound, height: f64, speed: f64) -> Player {
+        let mut player = Player::new(dvec3(0.0, height, 0.0), Team::Red);
+        player.velocity.y = -speed;
+        player.airborne = true;
+        for _ in 0..1000 {
+            player.collide(compound, &Movement::default());
+            if !player.airborne {
+                break;
             }
+        }
+        player
+    }
+
+    #[test]
+    fn lands_on_thin_platform_from_height() {
+        let compound = map(dvec3(0.0, 5.0, 0.0), dvec3(1.0, 0.1, 1.0));
+        let player = drop(&compound, 50.0, 0.0);
+        assert!(!player.airborne);
+        assert!((player.position.y - (5.1 + PLAYER_SIZE.y)).abs() < GROUND_PROBE);
+    }
+
+    #[test]
+    fn lands_on_thin_platform_when_falling_faster_than_it_is_thick() {
+        let compound = map(dvec3(0.0, 5.0, 0.0), dvec3(1.0, 0.1, 1.0));
+        for speed in [0.5, 2.0, 10.0] {
+            let player = drop(&compound, 50.0, speed);
+            assert!(
+                (player.position.y - (5.1 + PLAYER_SIZE.y)).abs() < GROUND_PROBE,
+                "fell through at {speed} per frame, ended at {}",
+                player.position.y,
+            );
+        }
+    }
+
+    #[test]
+    fn hits_ceiling_when_jumping_faster_than_it_is_thick() {
+        let compound = map(dvec3(0.0, 3.0, 0.0), dvec3(1.0, 0.1, 1.0));
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.velocity.y = 5.0;
+        player.airborne = true;
+        player.collide(&compound, &Movement::default());
+        assert!(player.position.y + PLAYER_SIZE.y <= 2.9 + GROUND_PROBE);
+    }
 
-            play_sound_once(bullet_sound);
+    #[test]
+    fn stops_at_thin_wall_when_moving_faster_than_it_is_thick() {
+        let compound = map(dvec3(1.0, 1.0, 0.0), dvec3(0.1, 1.0, 1.0));
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.velocity.x = 3.0;
+        player.collide(&compound, &Movement::default());
+        assert!(player.position.x + PLAYER_SIZE.x <= 0.9 + GROUND_PROBE);
+    }
+
+    #[test]
+    fn still_steps_onto_thin_platform() {
+        let compound = map(dvec3(1.0, 0.1, 0.0), dvec3(0.5, 0.1, 1.0));
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.velocity.x = MOVE_SPEED;
+        for _ in 0..20 {
+            player.collide(&compound, &Movement::default());
         }
+        assert!(!player.airborne);
+        assert!((player.position.y - (0.2 + PLAYER_SIZE.y)).abs() < GROUND_PROBE);
+    }
+
+    #[test]
+    fn walks_up_walkable_slopes() {
+        let compound = ramp(MAX_SLOPE * 0.6);
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.velocity.x = MOVE_SPEED;
+        for _ in 0..100 {
+            player.collide(&compound, &Movement::default());
+        }
+        assert!(!player.airborne);
+        assert!(player.position.y > PLAYER_SIZE.y + 1.0);
+    }
+
+    #[test]
+    fn doesnt_walk_up_steeper_slopes() {
+        let compound = ramp(MAX_SLOPE * 1.4);
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.velocity.x = MOVE_SPEED;
+        for _ in 0..100 {
+            player.collide(&compound, &Movement::default());
+        }
+        assert!(player.position.y < PLAYER_SIZE.y + STEP_HEIGHT);
+        assert!(player.position.x < 1.5);
+    }
+
+    #[test]
+    fn only_long_falls_hurt() {
+        let compound = map(dvec3(0.0, 5.0, 0.0), dvec3(1.0, 0.1, 1.0));
+        let short = drop(&compound, 5.1 + PLAYER_SIZE.y + 2.0, 0.0);
+        assert_eq!(short.health, MAX_HEALTH);
+        assert_eq!(short.hurt, 0.0);
+
+        let long = drop(&compound, 5.1 + PLAYER_SIZE.y + 6.0, 0.0);
+        assert!(long.health < MAX_HEALTH);
+        assert_eq!(long.hurt, MAX_HEALTH - long.health);
+        assert!(long.hard_landing_timestamp.is_some());
+
+        let lethal = drop(&compound, 50.0, 0.0);
+        assert!(lethal.killed);
+    }
+
+    /// A single trigger filling the box around the origin.
+    fn triggers(trigger: Trigger) -> Triggers {
+        Triggers::new(
+            vec![(Isometry::identity(), SharedShape::cuboid(1.0, 1.0, 1.0))],
+            vec![trigger],
+        )
+    }
+
+    #[test]
+    fn triggers_act_on_players_inside() {
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.trigger(&triggers(Trigger::JumpPad {
+            velocity: [0.0, 0.2, 0.0],
+        }));
+        assert!(player.airborne);
+        assert_eq!(player.velocity, dvec3(0.0, 0.2, 0.0));
+
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.trigger(&triggers(Trigger::Teleporter {
+            destination: [10.0, 0.0, 5.0],
+            yaw: None,
+        }));
+        assert_eq!(player.position, dvec3(10.0, PLAYER_SIZE.y, 5.0));
+
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.trigger(&triggers(Trigger::Kill));
+        assert!(player.killed);
+        assert_eq!(player.hurt, MAX_HEALTH);
+
+        let mut player = Player::new(dvec3(5.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.trigger(&triggers(Trigger::Kill));
+        assert!(!player.killed);
+    }
+
+    #[test]
+    fn events_fire_once_per_entry() {
+        let triggers = triggers(Trigger::Event {
+            name: "Mid".to_owned(),
+        });
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        for position in [0.0, 0.5, 5.0, 0.0] {
+            player.position.x = position;
+            player.trigger(&triggers);
+        }
+        assert_eq!(player.fired, ["Mid", "Mid"]);
+    }
+
+    /// A player holding the shipped default weapon, the pistol.
+    fn armed() -> (Player, Vec<Weapon>) {
+        let weapons = Weapon::parse_all(include_str!("../assets/weapons.toml"));
+        let mut player = Player::new(dvec3(0.0, PLAYER_SIZE.y, 0.0), Team::Red);
+        player.equip_defaults(&weapons);
+        (player, weapons)
+    }
+
+    /// Lets the reload in progress finish on the next call to `reloading`.
+    fn finish_reload(player: &mut Player, weapons: &[Weapon]) {
+        player.reload_timestamp = Some(Instant::now() - weapons[player.weapon].reload_duration);
+        player.reloading(&Controls::default(), weapons);
+    }
+
+    #[test]
+    fn reloads_only_whats_missing_from_the_reserve() {
+        let (mut player, weapons) = armed();
+        player.slot_mut().bullets_since_last_reload = 5;
+        assert!(player.start_reload());
+        assert_eq!(player.cues, [Cue::Reload]);
+        finish_reload(&mut player, &weapons);
+        assert!(player.reload_timestamp.is_none());
+        assert_eq!(player.slot().bullets_since_last_reload, 0);
+        assert_eq!(player.slot().reserve, 31);
+
+        player.slot_mut().bullets_since_last_reload = 12;
+        player.slot_mut().reserve = 4;
+        player.reloading(
+            &Controls {
+                reload: true,
+                ..Default::default()
+            },
+            &weapons,
+        );
+        finish_reload(&mut player, &weapons);
+        assert_eq!(player.slot().bullets_since_last_reload, 8);
+        assert_eq!(player.slot().reserve, 0);
+    }
+
+    #[test]
+    fn doesnt_reload_a_full_magazine_or_without_reserve() {
+        let (mut player, _) = armed();
+        assert!(!player.start_reload());
+
+        player.slot_mut().bullets_since_last_reload = 12;
+        player.slot_mut().reserve = 0;
+        assert!(!player.start_reload());
+        assert!(player.reload_timestamp.is_none());
+        assert!(player.cues.is_empty());
+    }
+
+    #[test]
+    fn switching_weapons_cancels_the_reload() {
+        let (mut player, weapons) = armed();
+        player.slots.push(Slot::new(1, &weapons));
+        player.slot_mut().bullets_since_last_reload = 5;
+        assert!(player.start_reload());
+
+        player.select(1);
+        assert!(player.reload_timestamp.is_none());
+        player.select(0);
+        assert_eq!(player.slot().bullets_since_last_reload, 5);
+        assert_eq!(player.slot().reserve, 36);
+    }
+
+    #[test]
+    fn pulling_the_trigger_on_an_empty_magazine_reloads() {
+        let (mut player, weapons) = armed();
+        let controls = Controls {
+            fire: true,
+            fire_pressed: true,
+            ..Default::default()
+        };
+        let peers = Arc::new(RwLock::new(HashMap::new()));
+
+        player.slot_mut().bullets_since_last_reload = 12;
+        let shot = player.bullets(
+            &controls,
+            &weapons,
+            &map(DVec3::ZERO, DVec3::ONE),
+            peers.clone(),
+            false,
+        );
+        assert!(shot.is_none());
+        assert!(player.reload_timestamp.is_some());
+        assert_eq!(player.cues, [Cue::Reload]);
+
+        // Without reserve there is nothing to reload, just the click
+        let (mut player, weapons) = armed();
+        player.slot_mut().bullets_since_last_reload = 12;
+        player.slot_mut().reserve = 0;
+        player.bullets(
+            &controls,
+            &weapons,
+            &map(DVec3::ZERO, DVec3::ONE),
+            peers,
+            false,
+        );
+        assert!(player.reload_timestamp.is_none());
+        assert_eq!(player.cues, [Cue::Empty]);
     }
 }
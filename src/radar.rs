@@ -0,0 +1,174 @@
+use crate::{
+    consts::*,
+    hud::{Anchor, Layout},
+    player::Player,
+    window::var,
+};
+use macroquad::prelude::*;
+use parry3d_f64::{
+    bounding_volume::Aabb,
+    math::{Point, Vector},
+    shape::Compound,
+};
+use std::{
+    collections::HashMap,
+    f32::consts::{FRAC_PI_2, SQRT_2},
+    net::SocketAddr,
+};
+
+/// Parses a number above zero.
+fn positive(value: &str) -> Option<f32> {
+    value.parse().ok().filter(|value: &f32| *value > 0.0)
+}
+
+/// Top-down view of the surroundings in the top-left corner, turned so that the player faces up.
+/// Teammates are always shown, enemies only for a while after they fire. Sized by the
+/// `RADAR_SIZE` (share of the screen height) and `RADAR_RANGE` (meters to the edge) environment
+/// variables, Z zooms while playing.
+pub struct Radar {
+    size: f32,
+    range: f32,
+    /// Index into `RADAR_ZOOMS`.
+    zoom: usize,
+}
+
+impl Radar {
+    pub fn from_env() -> Self {
+        Self {
+            size: var("RADAR_SIZE", positive).unwrap_or(DEFAULT_RADAR_SIZE),
+            range: var("RADAR_RANGE", positive).unwrap_or(DEFAULT_RADAR_RANGE),
+            zoom: 0,
+        }
+    }
+
+    /// Must be called every frame.
+    pub fn update(&mut self) {
+        if is_key_pressed(KeyCode::Z) {
+            self.zoom = (self.zoom + 1) % RADAR_ZOOMS.len();
+        }
+    }
+
+    pub fn draw(
+        &self,
+        layout: &Layout,
+        player: &Player,
+        peers: &HashMap<SocketAddr, Player>,
+        compound: &Compound,
+    ) {
+        let range = self.range * RADAR_ZOOMS[self.zoom];
+        let size = layout.size.y * self.size;
+        let corner = layout.place(Anchor::TOP_LEFT, Vec2::splat(HUD_MARGIN), Vec2::splat(size));
+        // Meters per pixel
+        let scale = range / (size / 2.0);
+
+        draw_rectangle(corner.x, corner.y, size, size, RADAR_BACKGROUND);
+
+        let dpi = screen_dpi_scale();
+
+        // World space on the floor, x and z
+        set_camera(&Camera2D {
+            target: vec2(player.position.x as f32, player.position.z as f32),
+            // Mirrored since the floor seen from above has z going right of x
+            zoom: Vec2::splat(-1.0 / range),
+            rotation: (FRAC_PI_2 - player.yaw as f32).to_degrees(),
+            // In physical pixels from the bottom left
+            viewport: Some((
+                (corner.x * dpi) as i32,
+                ((layout.size.y - corner.y - size) * dpi) as i32,
+                (size * dpi) as i32,
+                (size * dpi) as i32,
+            )),
+            ..Default::default()
+        });
+
+        // Whatever the rotation, the corners are no farther than this
+        let reach = (range * SQRT_2) as f64;
+        let mut nearby = Vec::new();
+        compound.qbvh().intersect_aabb(
+            &Aabb::new(
+                Point::new(
+                    player.position.x - reach,
+                    f64::MIN,
+                    player.position.z - reach,
+                ),
+                Point::new(
+                    player.position.x + reach,
+                    f64::MAX,
+                    player.position.z + reach,
+                ),
+            ),
+            &mut nearby,
+        );
+
+        for index in nearby {
+            let (isometry, shape) = &compound.shapes()[index as usize];
+            let Some(cuboid) = shape.as_cuboid() else {
+                let aabb = shape.compute_aabb(isometry);
+                draw_rectangle(
+                    aabb.mins.x as f32,
+                    aabb.mins.z as f32,
+                    (aabb.maxs.x - aabb.mins.x) as f32,
+                    (aabb.maxs.z - aabb.mins.z) as f32,
+                    RADAR_WALL_COLOR,
+                );
+                continue;
+            };
+
+            // Walls may be infinitely thin
+            let half_extents = cuboid.half_extents.add_scalar(scale as f64 / 2.0);
+            let corner = |signs: [f64; 3]| {
+                let point =
+                    isometry * Point::from(half_extents.component_mul(&Vector::from(signs)));
+                vec2(point.x as f32, point.z as f32)
+            };
+
+            // Seen from above, a rotated box is covered by its faces
+            for axis in 0..3 {
+                for sign in [-1.0, 1.0] {
+                    let [a, b, c, d] =
+                        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(u, v)| {
+                            let mut signs = [0.0; 3];
+                            signs[axis] = sign;
+                            signs[(axis + 1) % 3] = u;
+                            signs[(axis + 2) % 3] = v;
+                            corner(signs)
+                        });
+                    draw_triangle(a, b, c, RADAR_WALL_COLOR);
+                    draw_triangle(a, c, d, RADAR_WALL_COLOR);
+                }
+            }
+        }
+
+        for peer in peers.values() {
+            let revealed = peer.team == player.team
+                || peer
+                    .last_bullet_timestamp
+                    .is_some_and(|timestamp| timestamp.elapsed() < RADAR_REVEAL_DURATION);
+            if peer.killed || !revealed {
+                continue;
+            }
+
+            draw_circle(
+                peer.position.x as f32,
+                peer.position.z as f32,
+                RADAR_DOT_SIZE * layout.scale * scale,
+                peer.team.color(),
+            );
+        }
+
+        let position = vec2(player.position.x as f32, player.position.z as f32);
+        let front = vec2(player.front.x as f32, player.front.z as f32).normalize_or_zero();
+        let right = vec2(player.right.x as f32, player.right.z as f32).normalize_or_zero();
+        let dot = RADAR_DOT_SIZE * layout.scale * scale;
+        draw_triangle(
+            position + front * dot * 2.0,
+            position - front * dot + right * dot,
+            position - front * dot - right * dot,
+            WHITE,
+        );
+
+        set_default_camera();
+
+        draw_rectangle_lines(corner.x, corner.y, size, size, 2.0 * layout.scale, WHITE);
+    }
+}
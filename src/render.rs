@@ -0,0 +1,246 @@
+use crate::{consts::*, player::Player};
+use macroquad::{
+    miniquad::{UniformDesc, UniformType},
+    models::Vertex,
+    prelude::*,
+};
+
+const VERTEX: &str = r#"#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+attribute vec4 normal;
+
+varying lowp vec2 uv;
+varying lowp vec4 color;
+varying mediump vec3 world;
+varying mediump vec3 surface_normal;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    uv = texcoord;
+    world = position;
+    surface_normal = normal.xyz;
+}"#;
+
+const FRAGMENT: &str = r#"#version 100
+precision mediump float;
+
+varying lowp vec2 uv;
+varying lowp vec4 color;
+varying mediump vec3 world;
+varying mediump vec3 surface_normal;
+
+uniform sampler2D Texture;
+uniform vec3 LightDirection;
+uniform vec3 LightColor;
+uniform float Ambient;
+uniform vec3 FogColor;
+uniform float FogStart;
+uniform float FogEnd;
+uniform vec3 CameraPosition;
+
+void main() {
+    vec4 base = color * texture2D(Texture, fract(uv));
+    float light = Ambient + (1.0 - Ambient) * max(dot(normalize(surface_normal), LightDirection), 0.0);
+    float fog = clamp((distance(world, CameraPosition) - FogStart) / (FogEnd - FogStart), 0.0, 1.0);
+    gl_FragColor = vec4(mix(base.rgb * LightColor * light, FogColor, fog), base.a);
+}"#;
+
+/// Where a frame is seen and heard from.
+#[derive(Clone, Copy)]
+pub struct View {
+    pub eye: DVec3,
+    pub front: DVec3,
+    pub right: DVec3,
+    pub up: DVec3,
+}
+
+impl View {
+    pub fn new(eye: DVec3, yaw: f64, pitch: f64) -> Self {
+        let pitch = pitch.clamp(-PITCH_BOUND, PITCH_BOUND);
+        let front = dvec3(
+            yaw.cos() * pitch.cos(),
+            pitch.sin(),
+            yaw.sin() * pitch.cos(),
+        )
+        .normalize();
+        let right = front.cross(WORLD_UP).normalize();
+        Self {
+            eye,
+            front,
+            right,
+            up: right.cross(front).normalize(),
+        }
+    }
+
+    /// Through the eyes of `player`, recoil included.
+    pub fn of(player: &Player) -> Self {
+        Self {
+            eye: player.eye(),
+            front: player.front,
+            right: player.right,
+            up: player.up,
+        }
+    }
+
+    pub fn camera(&self) -> Camera3D {
+        Camera3D {
+            position: self.eye.as_vec3(),
+            up: self.up.as_vec3(),
+            target: (self.eye + self.front).as_vec3(),
+            fovy: FOV,
+            ..Default::default()
+        }
+    }
+}
+
+/// How a map is lit, the same for every surface drawn with `Lighting::material`.
+#[derive(Clone, Copy)]
+pub struct Light {
+    /// Towards the light.
+    pub direction: Vec3,
+    pub color: Color,
+    pub ambient: f32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Fog {
+    pub color: Color,
+    pub start: f32,
+    pub end: f32,
+}
+
+/// A material shading surfaces by a directional light and fading them into fog.
+pub struct Lighting {
+    material: Material,
+}
+
+impl Lighting {
+    pub fn new() -> Self {
+        let material = load_material(
+            ShaderSource::Glsl {
+                vertex: VERTEX,
+                fragment: FRAGMENT,
+            },
+            MaterialParams {
+                uniforms: vec![
+                    UniformDesc::new("LightDirection", UniformType::Float3),
+                    UniformDesc::new("LightColor", UniformType::Float3),
+                    UniformDesc::new("Ambient", UniformType::Float1),
+                    UniformDesc::new("FogColor", UniformType::Float3),
+                    UniformDesc::new("FogStart", UniformType::Float1),
+                    UniformDesc::new("FogEnd", UniformType::Float1),
+                    UniformDesc::new("CameraPosition", UniformType::Float3),
+                ],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        Self { material }
+    }
+
+    /// Everything drawn until `gl_use_default_material` is lit. Without fog nothing is faded.
+    pub fn apply(&self, light: &Light, fog: Option<&Fog>, camera: Vec3) {
+        let rgb = |color: Color| vec3(color.r, color.g, color.b);
+
+        self.material
+            .set_uniform("LightDirection", light.direction.normalize());
+        self.material.set_uniform("LightColor", rgb(light.color));
+        self.material.set_uniform("Ambient", light.ambient);
+        match fog {
+            Some(fog) => {
+                self.material.set_uniform("FogColor", rgb(fog.color));
+                self.material.set_uniform("FogStart", fog.start);
+                self.material
+                    .set_uniform("FogEnd", fog.end.max(fog.start + 1.0));
+            }
+            None => {
+                self.material.set_uniform("FogColor", Vec3::ZERO);
+                self.material.set_uniform("FogStart", f32::MAX / 2.0);
+                self.material.set_uniform("FogEnd", f32::MAX);
+            }
+        }
+        self.material.set_uniform("CameraPosition", camera);
+        gl_use_material(&self.material);
+    }
+}
+
+/// An oriented box with normals for `Lighting`. The texture repeats every `1 / texture_scale`
+/// meters rather than stretching over each face.
+pub fn box_mesh(
+    center: Vec3,
+    rotation: Quat,
+    half_extents: Vec3,
+    color: Color,
+    texture: Option<Texture2D>,
+    texture_scale: f32,
+) -> Mesh {
+    let axes = [Vec3::X, Vec3::Y, Vec3::Z];
+    let extents = half_extents.to_array();
+    let color: [u8; 4] = color.into();
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for face in 0..3 {
+        let [u, v] = [(face + 1) % 3, (face + 2) % 3];
+        for sign in [-1.0, 1.0] {
+            let normal = rotation * axes[face] * sign;
+            let first = vertices.len() as u16;
+            for (a, b) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let local = axes[face] * sign * extents[face]
+                    + axes[u] * a * extents[u]
+                    + axes[v] * b * extents[v];
+                vertices.push(Vertex {
+                    position: center + rotation * local,
+                    uv: vec2(a * extents[u], b * extents[v]) * texture_scale,
+                    color,
+                    normal: normal.extend(0.0),
+                });
+            }
+            indices.extend([0, 1, 2, 0, 2, 3].map(|index| first + index));
+        }
+    }
+
+    Mesh {
+        vertices,
+        indices,
+        texture,
+    }
+}
+
+/// A gradient from the horizon up around the camera, drawn first so that everything else covers
+/// it. Below the horizon is left to the background.
+pub fn draw_sky(camera: Vec3, top: Color, horizon: Color) {
+    const HALF_SIZE: f32 = 1000.0;
+
+    let mut vertices = Vec::with_capacity(8);
+    for y in [0.0, 1.0] {
+        for (x, z) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            vertices.push(Vertex::new2(
+                camera + vec3(x, y, z) * HALF_SIZE,
+                Vec2::ZERO,
+                if y > 0.0 { top } else { horizon },
+            ));
+        }
+    }
+
+    #[rustfmt::skip]
+    let indices = vec![
+        4, 5, 6, 4, 6, 7,
+        0, 1, 5, 0, 5, 4,
+        1, 2, 6, 1, 6, 5,
+        2, 3, 7, 2, 7, 6,
+        3, 0, 4, 3, 4, 7,
+    ];
+    draw_mesh(&Mesh {
+        vertices,
+        indices,
+        texture: None,
+    });
+}
@@ -0,0 +1,222 @@
+use crate::consts::*;
+use bincode::{Decode, Encode};
+use macroquad::prelude::*;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Encode, Decode)]
+pub enum Team {
+    Red,
+    Blue,
+}
+
+impl Team {
+    pub fn index(self) -> usize {
+        match self {
+            Team::Red => 0,
+            Team::Blue => 1,
+        }
+    }
+
+    /// Spawn zone the team uses, the sides are swapped every half.
+    pub fn side(self, swapped: bool) -> usize {
+        self.index() ^ swapped as usize
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Team::Red => RED,
+            Team::Blue => BLUE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Encode, Decode)]
+pub enum Phase {
+    /// Freeze time at the start of a round, nobody can move or shoot.
+    Buy,
+    Live,
+    /// `None` when the time ran out with both teams alive.
+    Over(Option<Team>),
+}
+
+/// What the host broadcasts so that everyone agrees on the round.
+#[derive(Clone, Copy, Encode, Decode)]
+pub struct RoundState {
+    pub number: u8,
+    pub phase: Phase,
+    pub remaining_millis: u64,
+    pub score: [u8; 2],
+}
+
+pub struct Round {
+    pub number: u8,
+    pub phase: Phase,
+    pub phase_end: Instant,
+    pub score: [u8; 2],
+}
+
+impl Round {
+    pub fn new() -> Self {
+        Self {
+            number: 1,
+            phase: Phase::Buy,
+            phase_end: Instant::now() + BUY_DURATION,
+            score: [0; 2],
+        }
+    }
+
+    pub fn swapped(&self) -> bool {
+        (self.number - 1) / ROUNDS_PER_HALF % 2 == 1
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.phase_end.saturating_duration_since(Instant::now())
+    }
+
+    pub fn state(&self) -> RoundState {
+        RoundState {
+            number: self.number,
+            phase: self.phase,
+            remaining_millis: self.remaining().as_millis() as u64,
+            score: self.score,
+        }
+    }
+
+    pub fn apply(&mut self, state: RoundState) {
+        self.number = state.number;
+        self.phase = state.phase;
+        self.phase_end = Instant::now() + Duration::from_millis(state.remaining_millis);
+        self.score = state.score;
+    }
+
+    /// Advances the round on the host. `present` and `alive` are player counts per team.
+    pub fn update(&mut self, present: [usize; 2], alive: [usize; 2]) {
+        let expired = Instant::now() >= self.phase_end;
+
+        match self.phase {
+            Phase::Buy if expired => {
+                self.phase = Phase::Live;
+                self.phase_end = Instant::now() + ROUND_DURATION;
+            }
+            Phase::Live => {
+                // A team that was never there can't be eliminated
+                let eliminated = [0, 1].map(|team| present[team] > 0 && alive[team] == 0);
+                let winner = match eliminated {
+                    [true, false] if present[1] > 0 => Some(Some(Team::Blue)),
+                    [false, true] if present[0] > 0 => Some(Some(Team::Red)),
+                    [true, true] => Some(None),
+                    _ if expired => Some(None),
+                    _ => None,
+                };
+
+                if let Some(winner) = winner {
+                    if let Some(team) = winner {
+                        self.score[team.index()] += 1;
+                    }
+                    self.phase = Phase::Over(winner);
+                    self.phase_end = Instant::now() + ROUND_OVER_DURATION;
+                }
+            }
+            Phase::Over(_) if expired => {
+                if self.score.iter().any(|&score| score >= ROUNDS_TO_WIN)
+                    || self.number >= ROUNDS_PER_HALF * 2
+                {
+                    self.number = 1;
+                    self.score = [0; 2];
+                } else {
+                    self.number += 1;
+                }
+                self.phase = Phase::Buy;
+                self.phase_end = Instant::now() + BUY_DURATION;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes the current phase run out.
+    fn expire(round: &mut Round) {
+        round.phase_end = Instant::now();
+    }
+
+    #[test]
+    fn goes_from_buy_to_live_to_over() {
+        let mut round = Round::new();
+        round.update([1, 1], [1, 1]);
+        assert_eq!(round.phase, Phase::Buy);
+
+        expire(&mut round);
+        round.update([1, 1], [1, 1]);
+        assert_eq!(round.phase, Phase::Live);
+
+        round.update([1, 1], [1, 0]);
+        assert_eq!(round.phase, Phase::Over(Some(Team::Red)));
+        assert_eq!(round.score, [1, 0]);
+
+        expire(&mut round);
+        round.update([1, 1], [1, 1]);
+        assert_eq!(round.phase, Phase::Buy);
+        assert_eq!(round.number, 2);
+    }
+
+    #[test]
+    fn running_out_of_time_is_a_draw() {
+        let mut round = Round::new();
+        round.phase = Phase::Live;
+        round.update([1, 1], [1, 1]);
+        assert_eq!(round.phase, Phase::Live);
+
+        expire(&mut round);
+        round.update([1, 1], [1, 1]);
+        assert_eq!(round.phase, Phase::Over(None));
+        assert_eq!(round.score, [0, 0]);
+    }
+
+    #[test]
+    fn eliminating_a_team_ends_the_round() {
+        let mut round = Round::new();
+        round.phase = Phase::Live;
+        round.update([2, 1], [0, 1]);
+        assert_eq!(round.phase, Phase::Over(Some(Team::Blue)));
+        assert_eq!(round.score, [0, 1]);
+
+        round.phase = Phase::Live;
+        round.update([1, 1], [0, 0]);
+        assert_eq!(round.phase, Phase::Over(None));
+
+        // Alone on the server, nobody wins by default
+        round.phase = Phase::Live;
+        round.update([1, 0], [1, 0]);
+        assert_eq!(round.phase, Phase::Live);
+    }
+
+    #[test]
+    fn sides_swap_at_half_time_and_the_match_restarts() {
+        let mut round = Round::new();
+        assert!(!round.swapped());
+        round.number = ROUNDS_PER_HALF;
+        assert!(!round.swapped());
+        round.number = ROUNDS_PER_HALF + 1;
+        assert!(round.swapped());
+
+        round.number = ROUNDS_PER_HALF * 2;
+        round.phase = Phase::Over(None);
+        round.score = [3, 4];
+        expire(&mut round);
+        round.update([1, 1], [1, 1]);
+        assert_eq!(round.number, 1);
+        assert_eq!(round.score, [0, 0]);
+        assert!(!round.swapped());
+
+        round.score = [ROUNDS_TO_WIN, 0];
+        round.phase = Phase::Over(Some(Team::Red));
+        expire(&mut round);
+        round.update([1, 1], [1, 1]);
+        assert_eq!(round.number, 1);
+        assert_eq!(round.score, [0, 0]);
+    }
+}
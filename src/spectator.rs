@@ -0,0 +1,274 @@
+use crate::{consts::*, player::Player, render::View, round::Team};
+use macroquad::prelude::*;
+use parry3d_f64::{
+    math::{Isometry, Point, Vector},
+    query::{Ray, RayCast},
+    shape::Compound,
+};
+use std::{collections::HashMap, net::SocketAddr};
+
+/// How a spectator watches.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    /// Flies around on its own.
+    Free,
+    /// Through the eyes of the followed player.
+    FirstPerson,
+    /// Orbits the followed player.
+    ThirdPerson,
+}
+
+impl Mode {
+    fn next(self) -> Self {
+        match self {
+            Mode::Free => Mode::FirstPerson,
+            Mode::FirstPerson => Mode::ThirdPerson,
+            Mode::ThirdPerson => Mode::Free,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Free => "free camera",
+            Mode::FirstPerson => "first person",
+            Mode::ThirdPerson => "third person",
+        }
+    }
+}
+
+/// The camera of someone who isn't playing, either because they only watch or because they
+/// died. Left and right click cycle the followed player, Space switches modes, and the free
+/// camera flies with WASD, E and Q.
+pub struct Spectator {
+    pub mode: Mode,
+    /// Followed player, kept while flying freely so that clicking goes on from it.
+    pub target: Option<SocketAddr>,
+    /// Of the free camera.
+    pub position: DVec3,
+    /// Where the free camera looks, and where the third-person camera looks from.
+    pub yaw: f64,
+    pub pitch: f64,
+    /// `None` until the first look, so that the view doesn't jump.
+    mouse_position: Option<DVec2>,
+}
+
+impl Spectator {
+    /// Starts flying freely from `view`.
+    pub fn new(view: View) -> Self {
+        let mut spectator = Self {
+            mode: Mode::Free,
+            target: None,
+            position: DVec3::ZERO,
+            yaw: 0.0,
+            pitch: 0.0,
+            mouse_position: None,
+        };
+        spectator.take_over(view);
+        spectator
+    }
+
+    /// Carries on from `view`, so that switching modes doesn't turn the camera around.
+    fn take_over(&mut self, view: View) {
+        self.position = view.eye;
+        self.yaw = view.front.z.atan2(view.front.x);
+        self.pitch = view.front.y.clamp(-1.0, 1.0).asin();
+    }
+
+    /// Players that can be followed, in a stable order: living ones, only of `team` if given.
+    pub fn targets(peers: &HashMap<SocketAddr, Player>, team: Option<Team>) -> Vec<SocketAddr> {
+        let mut targets = peers
+            .iter()
+            .filter(|(_, peer)| !peer.killed && team.is_none_or(|team| peer.team == team))
+            .map(|(peer_host, _)| *peer_host)
+            .collect::<Vec<_>>();
+        targets.sort_unstable();
+        targets
+    }
+
+    /// Follows the target `step` places on from the current one, wrapping around, or the first
+    /// one if the current one can't be followed.
+    fn cycle(&mut self, targets: &[SocketAddr], step: isize) {
+        let current = self
+            .target
+            .and_then(|target| targets.iter().position(|&other| other == target));
+        self.target = match current {
+            Some(index) => {
+                Some(targets[(index as isize + step).rem_euclid(targets.len() as isize) as usize])
+            }
+            None => targets.first().copied(),
+        };
+    }
+
+    pub fn update(
+        &mut self,
+        peers: &HashMap<SocketAddr, Player>,
+        team: Option<Team>,
+        compound: &Compound,
+        grabbed: bool,
+        delta: f64,
+    ) {
+        let targets = Self::targets(peers, team);
+        let view = self.view(peers, compound);
+
+        if grabbed {
+            let mouse_position: DVec2 = Vec2::from(mouse_position()).as_dvec2();
+            let mouse_delta = mouse_position - self.mouse_position.unwrap_or(mouse_position);
+            self.mouse_position = Some(mouse_position);
+
+            self.yaw += mouse_delta.x * delta * LOOK_SPEED;
+            self.pitch =
+                (self.pitch + mouse_delta.y * delta * -LOOK_SPEED).clamp(-PITCH_BOUND, PITCH_BOUND);
+        }
+
+        for (button, step) in [(MouseButton::Left, 1), (MouseButton::Right, -1)] {
+            if is_mouse_button_pressed(button) {
+                self.cycle(&targets, step);
+                if self.mode == Mode::Free && self.target.is_some() {
+                    self.mode = Mode::FirstPerson;
+                }
+            }
+        }
+        if is_key_pressed(KeyCode::Space) {
+            self.mode = self.mode.next();
+            self.take_over(view);
+        }
+
+        // The followed player died or left
+        if self.mode != Mode::Free && !self.target.is_some_and(|target| targets.contains(&target)) {
+            self.cycle(&targets, 1);
+            if self.target.is_none() {
+                self.mode = Mode::Free;
+                self.take_over(view);
+            }
+        }
+
+        if self.mode == Mode::Free {
+            let view = View::new(self.position, self.yaw, self.pitch);
+            let mut direction = DVec3::ZERO;
+            for (key, towards) in [
+                (KeyCode::W, view.front),
+                (KeyCode::S, -view.front),
+                (KeyCode::D, view.right),
+                (KeyCode::A, -view.right),
+                (KeyCode::E, WORLD_UP),
+                (KeyCode::Q, -WORLD_UP),
+            ] {
+                if is_key_down(key) {
+                    direction += towards;
+                }
+            }
+            let speed = if is_key_down(KeyCode::LeftShift) {
+                SPECTATOR_FAST_SPEED
+            } else {
+                SPECTATOR_SPEED
+            };
+            self.position += direction.normalize_or_zero() * speed;
+        }
+    }
+
+    /// The followed player, if any is being followed.
+    pub fn followed<'a>(&self, peers: &'a HashMap<SocketAddr, Player>) -> Option<&'a Player> {
+        match self.mode {
+            Mode::Free => None,
+            Mode::FirstPerson | Mode::ThirdPerson => peers.get(&self.target?),
+        }
+    }
+
+    pub fn view(&self, peers: &HashMap<SocketAddr, Player>, compound: &Compound) -> View {
+        match (self.mode, self.followed(peers)) {
+            // Replicated aim, so the view shows what they aim at
+            (Mode::FirstPerson, Some(target)) => View::new(target.eye(), target.yaw, target.pitch),
+            (Mode::ThirdPerson, Some(target)) => {
+                let center = target.eye();
+                let back = -View::new(center, self.yaw, self.pitch).front;
+                // Pulled in front of walls behind the target
+                let distance = compound
+                    .cast_ray(
+                        &Isometry::identity(),
+                        &Ray::new(
+                            Point::new(center.x, center.y, center.z),
+                            Vector::new(back.x, back.y, back.z),
+                        ),
+                        ORBIT_DISTANCE,
+                        true,
+                    )
+                    .map_or(ORBIT_DISTANCE, |toi| (toi - ORBIT_MARGIN).max(0.0));
+                View::new(center + back * distance, self.yaw, self.pitch)
+            }
+            _ => View::new(self.position, self.yaw, self.pitch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::isometry;
+    use parry3d_f64::shape::SharedShape;
+
+    fn peers() -> HashMap<SocketAddr, Player> {
+        [
+            (5001, Team::Red, false),
+            (5002, Team::Blue, false),
+            (5003, Team::Red, true),
+        ]
+        .into_iter()
+        .map(|(port, team, killed)| {
+            let mut peer = Player::new(dvec3(port as f64 - 5000.0, PLAYER_SIZE.y, 0.0), team);
+            peer.killed = killed;
+            (format!("127.0.0.1:{port}").parse().unwrap(), peer)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn cycles_through_living_players_both_ways() {
+        let peers = peers();
+        let targets = Spectator::targets(&peers, None);
+        assert_eq!(targets.len(), 2);
+
+        let mut spectator = Spectator::new(View::new(DVec3::ZERO, 0.0, 0.0));
+        spectator.cycle(&targets, 1);
+        assert_eq!(spectator.target, Some(targets[0]));
+        spectator.cycle(&targets, 1);
+        assert_eq!(spectator.target, Some(targets[1]));
+        spectator.cycle(&targets, 1);
+        assert_eq!(spectator.target, Some(targets[0]));
+        spectator.cycle(&targets, -1);
+        assert_eq!(spectator.target, Some(targets[1]));
+
+        // Dead players only follow their living teammates
+        assert_eq!(
+            Spectator::targets(&peers, Some(Team::Red)),
+            ["127.0.0.1:5001".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn orbit_stays_in_front_of_walls() {
+        let peers = peers();
+        let mut spectator = Spectator::new(View::new(DVec3::ZERO, 0.0, 0.0));
+        spectator.mode = Mode::ThirdPerson;
+        spectator.target = Spectator::targets(&peers, Some(Team::Blue))
+            .first()
+            .copied();
+        let target = spectator.followed(&peers).unwrap().eye();
+
+        let open = Compound::new(vec![(
+            isometry(dvec3(0.0, -1.0, 0.0), DQuat::IDENTITY),
+            SharedShape::cuboid(0.1, 0.1, 0.1),
+        )]);
+        let view = spectator.view(&peers, &open);
+        assert!((view.eye.distance(target) - ORBIT_DISTANCE).abs() < 1e-9);
+        assert!(view.front.dot(target - view.eye) > 0.0);
+
+        // A wall a meter behind the target
+        let walled = Compound::new(vec![(
+            isometry(target - DVec3::X, DQuat::IDENTITY),
+            SharedShape::cuboid(0.1, 5.0, 5.0),
+        )]);
+        let view = spectator.view(&peers, &walled);
+        assert!(view.eye.x > target.x - 0.9);
+        assert!(view.eye.x < target.x);
+    }
+}
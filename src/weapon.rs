@@ -0,0 +1,203 @@
+use crate::{
+    consts::*,
+    player::{Hit, Shot, Surface},
+};
+use bincode::{config, encode_to_vec};
+use macroquad::{file::load_string, prelude::*};
+use serde::{Deserialize, Deserializer};
+use std::{
+    net::{Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+pub fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    f64::deserialize(deserializer).map(Duration::from_secs_f64)
+}
+
+fn one() -> u8 {
+    1
+}
+
+/// Bytes the largest shot of `pellets` takes when sent, each pellet hurting someone else, along
+/// with the byte telling which event it is.
+fn largest_shot(pellets: u8) -> usize {
+    let victim = SocketAddr::from((Ipv6Addr::from(u128::MAX), u16::MAX));
+    let shot = Shot {
+        origin: [f64::MAX; 3],
+        pellets: vec![([f64::MAX; 3], Surface::Player); pellets as usize],
+        hits: vec![
+            Hit {
+                victim,
+                damage: f64::MAX,
+            };
+            pellets as usize
+        ],
+    };
+    encode_to_vec(shot, config::standard()).unwrap().len() + 1
+}
+
+#[derive(Deserialize)]
+struct Weapons {
+    weapon: Vec<Weapon>,
+}
+
+/// A gun as described in `assets/weapons.toml`.
+#[derive(Deserialize, Clone)]
+pub struct Weapon {
+    pub name: String,
+    #[serde(default)]
+    pub price: u32,
+    /// Handed out for free at the start of the match and after dying.
+    #[serde(default)]
+    pub default: bool,
+    #[serde(deserialize_with = "seconds")]
+    pub fire_interval: Duration,
+    pub magazine: u8,
+    pub reserve: u16,
+    #[serde(deserialize_with = "seconds")]
+    pub reload_duration: Duration,
+    /// `(seconds moving, spread)` points, linearly interpolated and clamped at both ends.
+    pub spread_curve: Vec<(f64, f64)>,
+    /// Spread of every pellet regardless of movement.
+    #[serde(default)]
+    pub pellet_spread: f64,
+    /// Per pellet.
+    pub damage: f64,
+    pub automatic: bool,
+    #[serde(default = "one")]
+    pub pellets: u8,
+    /// `(yaw, pitch)` kick in radians after every shot of a burst, the last one repeats.
+    #[serde(default)]
+    pub recoil_pattern: Vec<(f64, f64)>,
+    /// Radians per second the view returns by after a kick.
+    #[serde(default)]
+    pub recoil_recovery: f64,
+    /// Time without shooting after which a new burst starts.
+    #[serde(deserialize_with = "seconds")]
+    pub recoil_reset: Duration,
+}
+
+impl Weapon {
+    pub async fn load_all(path: &str) -> Vec<Self> {
+        Self::parse_all(&load_string(path).await.unwrap())
+    }
+
+    /// Panics on weapons the game can't work with, naming the first one.
+    pub(crate) fn parse_all(weapons: &str) -> Vec<Self> {
+        let weapons: Weapons = toml::from_str(weapons).expect("Invalid weapons file.");
+        assert!(!weapons.weapon.is_empty(), "No weapons defined.");
+        assert!(
+            weapons.weapon.iter().any(|weapon| weapon.default),
+            "No weapon has default = true, players would spawn empty-handed."
+        );
+        for weapon in &weapons.weapon {
+            assert!(
+                weapon
+                    .spread_curve
+                    .windows(2)
+                    .all(|points| points[0].0 < points[1].0),
+                "Spread curve of {} must have strictly increasing times.",
+                weapon.name
+            );
+            // Or the shooter would see hits that nobody else hears of
+            assert!(
+                largest_shot(weapon.pellets) <= PACKET_SIZE,
+                "{} fires too many pellets for a shot to fit in a packet.",
+                weapon.name
+            );
+        }
+        weapons.weapon
+    }
+
+    pub fn kick(&self, shot: usize) -> DVec2 {
+        self.recoil_pattern
+            .get(shot)
+            .or(self.recoil_pattern.last())
+            .map_or(DVec2::ZERO, |&(yaw, pitch)| dvec2(yaw, pitch))
+    }
+
+    pub fn spread(&self, moving_for: Duration) -> f64 {
+        let t = moving_for.as_secs_f64();
+
+        let Some(&(first_t, first_spread)) = self.spread_curve.first() else {
+            return 0.0;
+        };
+        if t <= first_t {
+            return first_spread;
+        }
+
+        for points in self.spread_curve.windows(2) {
+            let [(t0, spread0), (t1, spread1)] = [points[0], points[1]];
+            if t <= t1 {
+                return spread0 + (spread1 - spread0) * (t - t0) / (t1 - t0);
+            }
+        }
+
+        self.spread_curve.last().unwrap().1
+    }
+}
+
+/// A weapon the player owns along with its ammo.
+#[derive(Clone)]
+pub struct Slot {
+    /// Index into the weapon list.
+    pub weapon: usize,
+    pub bullets_since_last_reload: u8,
+    pub reserve: u16,
+}
+
+impl Slot {
+    pub fn new(weapon: usize, weapons: &[Weapon]) -> Self {
+        Self {
+            weapon,
+            bullets_since_last_reload: 0,
+            reserve: weapons[weapon].reserve,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PISTOL: &str = r#"
+        [[weapon]]
+        name = "Pistol"
+        default = true
+        fire_interval = 0.15
+        magazine = 12
+        reserve = 36
+        reload_duration = 1.5
+        spread_curve = [[0.0, 0.0], [1.0, 0.1]]
+        damage = 30.0
+        automatic = false
+        recoil_reset = 0.3
+    "#;
+
+    #[test]
+    fn loads_the_shipped_weapons() {
+        Weapon::parse_all(include_str!("../assets/weapons.toml"));
+        assert_eq!(
+            Weapon::parse_all(PISTOL)[0].spread(Duration::from_millis(500)),
+            0.05
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "No weapon has default = true")]
+    fn needs_a_default_weapon() {
+        Weapon::parse_all(&PISTOL.replace("default = true", "default = false"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Pistol fires too many pellets")]
+    fn needs_shots_that_fit_in_a_packet() {
+        Weapon::parse_all(&PISTOL.replace("automatic = false", "automatic = false\npellets = 255"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Spread curve of Pistol")]
+    fn needs_an_increasing_spread_curve() {
+        Weapon::parse_all(&PISTOL.replace("[1.0, 0.1]", "[0.0, 0.1]"));
+    }
+}
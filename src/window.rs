@@ -0,0 +1,130 @@
+use macroquad::{
+    miniquad::{
+        self,
+        window::{set_window_position, set_window_size},
+    },
+    prelude::*,
+};
+use std::env::vars;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Windowed,
+    /// A window without the fullscreen state covering the whole display, at its top left.
+    Borderless,
+    Fullscreen,
+}
+
+impl Mode {
+    fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "windowed" => Some(Self::Windowed),
+            "borderless" => Some(Self::Borderless),
+            "fullscreen" => Some(Self::Fullscreen),
+            _ => None,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Self::Windowed => Self::Borderless,
+            Self::Borderless => Self::Fullscreen,
+            Self::Fullscreen => Self::Windowed,
+        }
+    }
+}
+
+/// Parses `<a><separator><b>`, such as `1280x720`.
+fn pair(value: &str, separator: char) -> Option<(u32, u32)> {
+    let (a, b) = value.split_once(separator)?;
+    Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+}
+
+/// Reads an environment variable, warning when it's set but can't be parsed.
+pub fn var<T>(key: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    let (_, value) = vars().find(|(name, _)| name == key)?;
+    let parsed = parse(&value);
+    if parsed.is_none() {
+        eprintln!("Warning: ignoring invalid {key}={value}");
+    }
+    parsed
+}
+
+/// How the game window is shown, from the `WINDOW` (`windowed`, `borderless` or `fullscreen`),
+/// `WINDOW_SIZE` (`<width>x<height>`) and `WINDOW_POSITION` (`<x>,<y>`) environment variables.
+/// F11 cycles through the modes while playing.
+pub struct WindowSettings {
+    pub mode: Mode,
+    /// Of the windowed mode, in physical pixels.
+    pub size: (u32, u32),
+    pub position: Option<(u32, u32)>,
+    /// Size of the display, known once the window has been fullscreen.
+    display: Option<(u32, u32)>,
+}
+
+impl WindowSettings {
+    pub fn from_env() -> Self {
+        Self {
+            mode: var("WINDOW", Mode::parse).unwrap_or(Mode::Fullscreen),
+            size: var("WINDOW_SIZE", |value| pair(value, 'x')).unwrap_or((1280, 720)),
+            position: var("WINDOW_POSITION", |value| pair(value, ',')),
+            display: None,
+        }
+    }
+
+    pub fn conf(&self) -> Conf {
+        Conf {
+            window_title: "librego".to_owned(),
+            window_width: self.size.0 as i32,
+            window_height: self.size.1 as i32,
+            fullscreen: self.mode != Mode::Windowed,
+            window_resizable: true,
+            platform: miniquad::conf::Platform {
+                linux_backend: miniquad::conf::LinuxBackend::WaylandWithX11Fallback,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Some window managers ignore requests made right after the window opens, so this is
+    /// repeated for the first few frames.
+    pub fn apply(&self) {
+        match self.mode {
+            Mode::Windowed => {
+                set_fullscreen(false);
+                set_window_size(self.size.0, self.size.1);
+                if let Some((x, y)) = self.position {
+                    set_window_position(x, y);
+                }
+            }
+            Mode::Borderless => {
+                set_fullscreen(false);
+                // Without a known display size this is the best there is
+                let Some((width, height)) = self.display else {
+                    set_fullscreen(true);
+                    return;
+                };
+                set_window_size(width, height);
+                set_window_position(0, 0);
+            }
+            Mode::Fullscreen => set_fullscreen(true),
+        }
+    }
+
+    /// Must be called every frame.
+    pub fn update(&mut self) {
+        if self.mode != Mode::Windowed {
+            let dpi = screen_dpi_scale();
+            self.display = Some((
+                (screen_width() * dpi) as u32,
+                (screen_height() * dpi) as u32,
+            ));
+        }
+
+        if is_key_pressed(KeyCode::F11) {
+            self.mode = self.mode.next();
+            self.apply();
+        }
+    }
+}